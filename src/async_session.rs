@@ -0,0 +1,781 @@
+use crate::{
+    batch_request_id, duplicate_batch_ids, invalid_request, request_deadline, strip_bom,
+    timeout_response, AsyncTransport, ByteStream, Cancellation, ErrorCode, ErrorResponse,
+    LinesTransport, McpError, Recorded, RequestId, Response, Session,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufRead, AsyncWrite};
+
+/// The order of the responses to a batch handled in the async path, where
+/// its tool calls run concurrently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchOrdering {
+    /// In the order of the requests, for clients that expect it.
+    #[default]
+    Preserve,
+    /// The batch's `tools/call` responses in the order the calls finish,
+    /// after the other responses. The spec allows this, since clients
+    /// correlate responses by id. Other requests in the batch run one at a
+    /// time and keep their request order, so only tool calls are reordered.
+    AsCompleted,
+}
+
+impl Session {
+    /// Bounds how long a request may run in the async path before the client
+    /// gets a timeout error. Individual tools can override this.
+    pub fn set_request_timeout(&self, timeout: Duration) {
+        self.state.lock().unwrap().request_timeout = Some(timeout);
+    }
+
+    /// Caps how many requests run at once in the async path; further ones wait
+    /// for a slot before starting.
+    pub fn set_max_concurrency(&self, limit: usize) {
+        self.state.lock().unwrap().concurrency_limit =
+            Some(Arc::new(tokio::sync::Semaphore::new(limit.max(1))));
+    }
+
+    /// Chooses how the responses to a batch are ordered in the async path.
+    pub fn set_batch_ordering(&self, ordering: BatchOrdering) {
+        self.state.lock().unwrap().batch_ordering = ordering;
+    }
+
+    /// Async counterpart of `handle_message`; see `handle_value_async`.
+    pub async fn handle_message_async(&self, message: &str) -> Result<Option<String>, McpError> {
+        let value = match serde_json::from_str(strip_bom(message)) {
+            Ok(value) => value,
+            Err(err) => return Ok(Some(self.to_json(&self.answer_unparsed(message, &err)?)?)),
+        };
+        match self.handle_value_async(value).await? {
+            Some(response) => Ok(Some(self.to_json(&response)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Handles a message without blocking the async runtime. Requests run on
+    /// the blocking thread pool and are answered with a timeout error once the
+    /// applicable timeout (the tool's own, else the session's) elapses. The
+    /// handler itself keeps running in the background but is marked cancelled,
+    /// so it can stop early by polling `RequestContext::is_cancelled`. A request
+    /// the client cancels with `notifications/cancelled` is dropped the same
+    /// way, without any response. The `tools/call` requests of a batch run
+    /// concurrently.
+    pub async fn handle_value_async(&self, value: Value) -> Result<Option<Value>, McpError> {
+        let inbound = self
+            .is_recording()
+            .then(|| Recorded::Inbound(value.clone()));
+        let response = self.answer_value_async(value).await?;
+        if let Some(inbound) = inbound {
+            self.record(inbound, response.clone())?;
+        }
+        Ok(response)
+    }
+
+    // The parse error answering `message`, recorded like the sync path does
+    fn answer_unparsed(&self, message: &str, err: &serde_json::Error) -> Result<Value, McpError> {
+        let response = serde_json::to_value(self.parse_error(err))?;
+        if self.is_recording() {
+            self.record(
+                Recorded::Unparsed(message.to_string()),
+                Some(response.clone()),
+            )?;
+        }
+        Ok(response)
+    }
+
+    async fn answer_value_async(&self, value: Value) -> Result<Option<Value>, McpError> {
+        if let Value::Array(batch) = value {
+            return self.handle_batch_async(batch).await;
+        }
+        match batch_request_id(&value) {
+            Some(id) => self.run_request(id, value).await,
+            // Notifications and responses are cheap and never time out
+            None => self.answer_value(value),
+        }
+    }
+
+    // Like the sync batch handling, but tool calls are started together and
+    // their responses slotted back in request order as they finish, unless
+    // the session asked for them in completion order.
+    async fn handle_batch_async(&self, batch: Vec<Value>) -> Result<Option<Value>, McpError> {
+        // Batches rejected as a whole are answered the same way as in the sync path
+        let max = self.state.lock().unwrap().max_batch_size;
+        if batch.is_empty() || batch.len() > max {
+            return self.answer_value(Value::Array(batch));
+        }
+
+        let ordering = self.state.lock().unwrap().batch_ordering;
+        let duplicates = duplicate_batch_ids(&batch);
+        let mut responses = Vec::with_capacity(batch.len());
+        let mut calls = tokio::task::JoinSet::new();
+        // The slot, id and registration of each call, for when its task fails
+        let mut slots = HashMap::new();
+        for (index, message) in batch.into_iter().enumerate() {
+            match batch_request_id(&message) {
+                Some(id) if duplicates.contains(&id) => {
+                    let response = invalid_request(id, "Duplicate request id in batch");
+                    responses.push(Some(serde_json::to_value(response)?));
+                }
+                Some(id) if message["method"] == "tools/call" => {
+                    responses.push(None);
+                    // Registered before the task starts so an early cancel reaches it
+                    let cancellation = self.state.lock().unwrap().begin_request(&id);
+                    let session = self.share();
+                    let answering = id.clone();
+                    let task = calls.spawn(async move {
+                        let response = session.run_request(answering.clone(), message).await;
+                        response.unwrap_or_else(|err| Some(failure(answering, &err)))
+                    });
+                    slots.insert(task.id(), (index, id, cancellation));
+                }
+                // A failing entry is answered on its own, like a malformed one
+                Some(id) => {
+                    let response = self.run_request(id.clone(), message).await;
+                    responses.push(response.unwrap_or_else(|err| Some(failure(id, &err))));
+                }
+                None => {
+                    let id = message_id(&message);
+                    let response = self.answer_value(message);
+                    responses.push(response.unwrap_or_else(|err| Some(failure(id, &err))));
+                }
+            }
+        }
+        while let Some(joined) = calls.join_next_with_id().await {
+            let task = match &joined {
+                Ok((task, _)) => *task,
+                Err(err) => err.id(),
+            };
+            let (index, id, cancellation) = slots.remove(&task).expect("every call has a slot");
+            let response = match joined {
+                Ok((_, response)) => response,
+                // A call whose task panicked or was aborted fails on its own
+                Err(err) => {
+                    self.state
+                        .lock()
+                        .unwrap()
+                        .finish_request(&id, &cancellation);
+                    Some(failure(id, &McpError::Io(std::io::Error::other(err))))
+                }
+            };
+            match ordering {
+                BatchOrdering::Preserve => responses[index] = response,
+                BatchOrdering::AsCompleted => responses.push(response),
+            }
+        }
+
+        let responses: Vec<Value> = responses.into_iter().flatten().collect();
+        Ok((!responses.is_empty()).then_some(Value::Array(responses)))
+    }
+
+    // Runs a request unless the client cancels it first, in which case it gets
+    // no response
+    async fn run_request(&self, id: RequestId, value: Value) -> Result<Option<Value>, McpError> {
+        let cancellation = self.state.lock().unwrap().begin_request(&id);
+        // Dropping the losing branch detaches the blocking handler, which can
+        // still see it was cancelled through `RequestContext::is_cancelled`.
+        // A cancel that came before the request started wins outright.
+        let outcome = tokio::select! {
+            biased;
+            _ = cancellation.abort.notified() => Ok(None),
+            response = self.run_to_completion(id.clone(), value, &cancellation) => response.map(Some),
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .finish_request(&id, &cancellation);
+        outcome
+    }
+
+    async fn run_to_completion(
+        &self,
+        id: RequestId,
+        value: Value,
+        cancellation: &Cancellation,
+    ) -> Result<Value, McpError> {
+        let limit = self.state.lock().unwrap().concurrency_limit.clone();
+        let _permit = match limit {
+            Some(limit) => Some(
+                limit
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed"),
+            ),
+            None => None,
+        };
+
+        let timeout = self.timeout_for(&value);
+        let session = self.share();
+        let task = tokio::task::spawn_blocking(move || session.answer_value(value));
+        let joined = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, task).await {
+                Ok(joined) => joined,
+                Err(_) => {
+                    cancellation.cancel();
+                    return Ok(serde_json::to_value(timeout_response(id))?);
+                }
+            },
+            None => task.await,
+        };
+        let response = joined.map_err(|err| McpError::Io(std::io::Error::other(err)))??;
+        // A request always yields a response
+        Ok(response.unwrap_or(Value::Null))
+    }
+
+    /// Serves newline-delimited JSON-RPC from `input` to `output` until the
+    /// input ends or the client sends `exit`. Requests run concurrently, so a
+    /// handler waiting on the answer to its own `send_request` doesn't stop
+    /// the loop from reading that answer, or from serving other requests. Queued
+    /// server-initiated requests and notifications are written as they appear.
+    /// A message that fails is answered with an error; only I/O errors end the
+    /// loop early.
+    pub async fn serve_async<R, W>(&self, input: R, output: W) -> Result<(), McpError>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.serve_transport_async(LinesTransport::new(input, output))
+            .await
+    }
+
+    /// Like `serve_async`, over any `AsyncTransport`.
+    pub async fn serve_transport_async<T: AsyncTransport>(
+        &self,
+        mut transport: T,
+    ) -> Result<(), McpError> {
+        let outbox_ready = Arc::clone(&self.state.lock().unwrap().outbox_ready);
+        let mut requests = tokio::task::JoinSet::new();
+        // The id each running task answers, for when the task itself fails
+        let mut request_ids = HashMap::new();
+        let mut reading = true;
+        while reading || !requests.is_empty() {
+            tokio::select! {
+                line = transport.receive(), if reading => match line? {
+                    Some(line) => match serde_json::from_str::<Value>(strip_bom(&line)) {
+                        // Batches may hold requests too, so they run alongside
+                        // the reader rather than on it
+                        Ok(value) if value.is_array() || (value.get("id").is_some() && value.get("method").is_some()) => {
+                            let id = message_id(&value);
+                            let registered = self.register_requests(&value);
+                            let session = self.share();
+                            let answering = id.clone();
+                            let task = requests.spawn(async move {
+                                let response = session.handle_value_async(value).await;
+                                let mut state = session.state.lock().unwrap();
+                                for (id, cancellation) in &registered {
+                                    state.finish_request(id, cancellation);
+                                }
+                                drop(state);
+                                response.unwrap_or_else(|err| Some(failure(answering, &err)))
+                            });
+                            request_ids.insert(task.id(), id);
+                        }
+                        // Responses and notifications are handled inline so a
+                        // waiting handler is released right away
+                        Ok(value) => {
+                            let id = message_id(&value);
+                            let response = self.handle_value_async(value).await;
+                            if let Some(response) = response.unwrap_or_else(|err| Some(failure(id, &err))) {
+                                transport.send(&self.to_json(&response)?).await?;
+                            }
+                        }
+                        Err(err) => transport.send(&self.to_json(&self.answer_unparsed(&line, &err)?)?).await?,
+                    },
+                    None => reading = false,
+                },
+                Some(joined) = requests.join_next_with_id() => {
+                    let response = match joined {
+                        Ok((task, response)) => {
+                            request_ids.remove(&task);
+                            response
+                        }
+                        Err(err) => {
+                            let id = request_ids.remove(&err.id()).unwrap_or(RequestId::Null);
+                            Some(failure(id, &McpError::Io(std::io::Error::other(err))))
+                        }
+                    };
+                    if let Some(response) = response {
+                        transport.send(&self.to_json(&response)?).await?;
+                    }
+                }
+                _ = outbox_ready.notified() => {}
+            }
+
+            for request in self.drain_outgoing_requests() {
+                transport.send(&self.to_json(&request)?).await?;
+            }
+            for notification in self.drain_notifications() {
+                transport.send(&self.to_json(&notification)?).await?;
+            }
+            transport.flush().await?;
+
+            if self.should_exit() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Registers the requests in `message` before it is handed to another task,
+    // so a cancellation read right after it can't miss them. Whatever of them
+    // didn't run is unregistered with the returned handles.
+    fn register_requests(&self, message: &Value) -> Vec<(RequestId, Arc<Cancellation>)> {
+        let entries = match message {
+            Value::Array(batch) => batch.iter().collect(),
+            message => vec![message],
+        };
+        let mut state = self.state.lock().unwrap();
+        entries
+            .into_iter()
+            .filter_map(batch_request_id)
+            .map(|id| {
+                let cancellation = state.begin_request(&id);
+                (id, cancellation)
+            })
+            .collect()
+    }
+
+    fn timeout_for(&self, request: &Value) -> Option<Duration> {
+        let tool_timeout = (request["method"] == "tools/call")
+            .then(|| request["params"]["name"].as_str())
+            .flatten()
+            .and_then(|name| self.tools.iter().find(|entry| entry.tool.name == name))
+            .and_then(|entry| entry.timeout);
+        // A deadline from the client overrides the configured timeouts
+        let until_deadline = request_deadline(request.get("params")).map(|deadline| {
+            deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO)
+        });
+        until_deadline
+            .or(tool_timeout)
+            .or(self.state.lock().unwrap().request_timeout)
+    }
+
+    // A second handle onto this session's core and state that can be moved
+    // onto another thread. Watchers stay with the original session.
+    fn share(&self) -> Session {
+        Session {
+            core: Arc::clone(&self.core),
+            state: Arc::clone(&self.state),
+            #[cfg(feature = "watch")]
+            watchers: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+}
+
+// Wakes the thread draining a stream in `drain_stream`
+struct ThreadWaker(std::thread::Thread);
+
+impl std::task::Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Collects a streamed resource body on the calling thread, for the sync paths
+// that have to return it whole
+pub(crate) fn drain_stream(mut stream: ByteStream) -> std::io::Result<Vec<u8>> {
+    let waker = std::task::Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut data = Vec::new();
+    loop {
+        match stream.as_mut().poll_next(&mut cx) {
+            Poll::Ready(Some(chunk)) => data.extend_from_slice(&chunk?),
+            Poll::Ready(None) => return Ok(data),
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+// The id of a single message, or null when it has none that can be echoed
+fn message_id(message: &Value) -> RequestId {
+    serde_json::from_value(message["id"].clone()).unwrap_or(RequestId::Null)
+}
+
+// The answer to a message whose handling failed, so that one bad message
+// doesn't end the serve loop or the rest of its batch
+fn failure(id: RequestId, err: &McpError) -> Value {
+    let code = match err {
+        McpError::Io(_) => ErrorCode::InternalError,
+        McpError::Json(_) | McpError::Config(_) => ErrorCode::InvalidRequest,
+    };
+    let response = Response::from_result(id, Err(ErrorResponse::new(code, err.to_string())));
+    serde_json::to_value(response).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::initialized;
+    use crate::{BatchOrdering, Content, Server, Tool};
+    use serde_json::Value;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+
+    fn sleepy_tool(name: &str) -> Tool {
+        Tool {
+            name: name.to_string(),
+            description: None,
+            input_schema: serde_json::json!({ "type": "object" }),
+            annotations: None,
+            meta: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_per_tool_timeout_overrides_default() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool_with_timeout(
+            sleepy_tool("patient"),
+            Some(Duration::from_secs(5)),
+            |_, _| {
+                std::thread::sleep(Duration::from_millis(200));
+                Ok(Vec::new())
+            },
+        );
+        server.register_tool(sleepy_tool("hasty"), |_, _| {
+            std::thread::sleep(Duration::from_millis(200));
+            Ok(Vec::new())
+        });
+        server.set_request_timeout(Duration::from_millis(50));
+
+        let call = |name: &str| {
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": name }
+            })
+        };
+
+        let response = server
+            .handle_value_async(call("patient"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response["result"]["isError"], false);
+
+        let response = server
+            .handle_value_async(call("hasty"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response["error"]["code"], -32001);
+        assert_eq!(response["id"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_deadline_overrides_default_timeout() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(sleepy_tool("slow"), |_, _| {
+            std::thread::sleep(Duration::from_millis(200));
+            Ok(Vec::new())
+        });
+        server.set_request_timeout(Duration::from_millis(50));
+        let call = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "slow", "_meta": { "deadline": "2999-01-01T00:00:00Z" } }
+        });
+
+        let response = server.handle_value_async(call).await.unwrap().unwrap();
+        assert_eq!(response["result"]["isError"], false);
+    }
+
+    async fn write_message<W: AsyncWrite + Unpin>(output: &mut W, message: Value) {
+        let mut line = message.to_string();
+        line.push('\n');
+        output.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    async fn read_message<R: AsyncBufRead + Unpin>(lines: &mut Lines<R>) -> Value {
+        serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap()
+    }
+
+    // A server whose `summarize` tool waits on a sampling request to the client
+    fn summarizing_server() -> Server {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(sleepy_tool("summarize"), |_, ctx| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            ctx.send_request(
+                "sampling/createMessage",
+                Some(serde_json::json!({ "messages": [], "maxTokens": 10 })),
+                move |result| sender.send(result).unwrap(),
+            );
+            let result = receiver
+                .recv_timeout(Duration::from_secs(5))?
+                .map_err(|err| err.message().to_string())?;
+            Ok(vec![Content::Text {
+                text: result["content"]["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            }])
+        });
+        server
+    }
+
+    #[tokio::test]
+    async fn test_serve_interleaves_server_requests() {
+        let server = summarizing_server();
+        let (client, server_io) = tokio::io::duplex(4096);
+        let (server_read, server_write) = tokio::io::split(server_io);
+        let serving = tokio::spawn(async move {
+            server
+                .serve_async(BufReader::new(server_read), server_write)
+                .await
+        });
+
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut lines = BufReader::new(client_read).lines();
+
+        write_message(
+            &mut client_write,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "summarize" }
+            }),
+        )
+        .await;
+        let sampling: Value = read_message(&mut lines).await;
+        assert_eq!(sampling["method"], "sampling/createMessage");
+
+        // The tool is still waiting, but other requests are answered meanwhile
+        write_message(
+            &mut client_write,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" }),
+        )
+        .await;
+        let pong: Value = read_message(&mut lines).await;
+        assert_eq!(pong["id"], 2);
+
+        write_message(
+            &mut client_write,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": sampling["id"],
+                "result": { "role": "assistant", "content": { "type": "text", "text": "short" } }
+            }),
+        )
+        .await;
+        let result: Value = read_message(&mut lines).await;
+        assert_eq!(result["id"], 1);
+        assert_eq!(result["result"]["content"][0]["text"], "short");
+
+        // Both halves have to go for the server to see the input end
+        drop((lines, client_write));
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_answers_bad_messages_and_batches() {
+        let server = summarizing_server();
+        let (client, server_io) = tokio::io::duplex(4096);
+        let (server_read, server_write) = tokio::io::split(server_io);
+        let serving = tokio::spawn(async move {
+            server
+                .serve_async(BufReader::new(server_read), server_write)
+                .await
+        });
+
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut lines = BufReader::new(client_read).lines();
+
+        write_message(
+            &mut client_write,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 9 }),
+        )
+        .await;
+        let rejected: Value = read_message(&mut lines).await;
+        assert_eq!(rejected["id"], 9);
+        assert_eq!(rejected["error"]["code"], -32600);
+
+        // A batch whose tool waits on the client doesn't stop the reader
+        write_message(
+            &mut client_write,
+            serde_json::json!([{
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "summarize" }
+            }]),
+        )
+        .await;
+        let sampling: Value = read_message(&mut lines).await;
+        assert_eq!(sampling["method"], "sampling/createMessage");
+        write_message(
+            &mut client_write,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": sampling["id"],
+                "result": { "role": "assistant", "content": { "type": "text", "text": "short" } }
+            }),
+        )
+        .await;
+        let batch: Value = read_message(&mut lines).await;
+        assert_eq!(batch[0]["id"], 1);
+        assert_eq!(batch[0]["result"]["content"][0]["text"], "short");
+
+        drop((lines, client_write));
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_tool_calls_run_concurrently() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // How many calls run at once, and the most seen, rather than timings
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        for (name, millis) in [("slow", 300), ("quick", 100)] {
+            let (running, peak) = (Arc::clone(&running), Arc::clone(&peak));
+            server.register_tool(sleepy_tool(name), move |_, _| {
+                peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(millis));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(vec![Content::Text {
+                    text: name.to_string(),
+                }])
+            });
+        }
+        let batch = serde_json::json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": { "name": "slow" } },
+            { "jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": { "name": "quick" } }
+        ]);
+
+        let responses = server
+            .handle_value_async(batch.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        // Responses follow the batch order even though `quick` finished first
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["result"]["content"][0]["text"], "slow");
+        assert_eq!(responses[1]["result"]["content"][0]["text"], "quick");
+
+        server.set_max_concurrency(1);
+        peak.store(0, Ordering::SeqCst);
+        server.handle_value_async(batch).await.unwrap().unwrap();
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_aborts_request() {
+        let (stopped, stopping) = std::sync::mpsc::channel();
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(sleepy_tool("endless"), move |_, ctx| {
+            let started = std::time::Instant::now();
+            while !ctx.is_cancelled() && started.elapsed() < Duration::from_secs(5) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            stopped.send(ctx.is_cancelled()).unwrap();
+            Ok(Vec::new())
+        });
+        let call = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "tools/call",
+            "params": { "name": "endless" }
+        });
+
+        let session = server.share();
+        let started = std::time::Instant::now();
+        let request = tokio::spawn(async move { session.handle_value_async(call).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let cancel = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": { "requestId": 7 }
+        });
+        assert_eq!(server.handle_value_async(cancel).await.unwrap(), None);
+
+        assert_eq!(request.await.unwrap().unwrap(), None);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // The handler itself was told to stop, and the id is forgotten
+        assert!(stopping.recv_timeout(Duration::from_secs(1)).unwrap());
+        assert!(server.state.lock().unwrap().in_flight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_serve_honours_cancel_read_with_its_request() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(sleepy_tool("quick"), |_, _| Ok(Vec::new()));
+        let session = server.share();
+
+        let (client, server_io) = tokio::io::duplex(4096);
+        let (server_read, server_write) = tokio::io::split(server_io);
+        let serving = tokio::spawn(async move {
+            server
+                .serve_async(BufReader::new(server_read), server_write)
+                .await
+        });
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut lines = BufReader::new(client_read).lines();
+
+        // Both arrive together, so the cancel is read before the request's
+        // task has had a chance to run
+        let call = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "quick" }
+        });
+        let cancel = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": { "requestId": 1 }
+        });
+        let input = format!("{call}\n{cancel}\n");
+        client_write.write_all(input.as_bytes()).await.unwrap();
+        write_message(
+            &mut client_write,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" }),
+        )
+        .await;
+        let pong: Value = read_message(&mut lines).await;
+        assert_eq!(pong["id"], 2);
+
+        drop((lines, client_write));
+        serving.await.unwrap().unwrap();
+        assert!(session.state.lock().unwrap().in_flight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batch_ordering() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        for (name, millis) in [("slow", 200), ("quick", 10)] {
+            server.register_tool(sleepy_tool(name), move |_, _| {
+                std::thread::sleep(Duration::from_millis(millis));
+                Ok(Vec::new())
+            });
+        }
+        let batch = serde_json::json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": { "name": "slow" } },
+            { "jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": { "name": "quick" } },
+            { "jsonrpc": "2.0", "id": 3, "method": "ping" }
+        ]);
+        let ids = |responses: Value| -> Vec<Value> {
+            let responses = responses.as_array().unwrap();
+            responses
+                .iter()
+                .map(|response| response["id"].clone())
+                .collect()
+        };
+
+        let responses = server.handle_value_async(batch.clone()).await.unwrap();
+        assert_eq!(ids(responses.unwrap()), [1, 2, 3]);
+
+        server.set_batch_ordering(BatchOrdering::AsCompleted);
+        let responses = server.handle_value_async(batch).await.unwrap();
+        // `ping` runs inline while the tool calls are in flight
+        assert_eq!(ids(responses.unwrap()), [3, 2, 1]);
+    }
+}
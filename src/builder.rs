@@ -0,0 +1,283 @@
+use crate::{
+    BuildError, Capability, Content, Diagnostic, HandlerResult, Inconsistency, Prompt,
+    PromptResult, RequestContext, Resource, ResourceContents, ResourceTemplate, Server,
+    ServerCapabilities, ServerCore, Tool,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Assembles a server and checks, on `build`, that every advertised capability
+/// has something to serve it and every registration is reachable.
+pub struct ServerBuilder {
+    core: ServerCore,
+    allow_inconsistent: bool,
+    // Registrations that failed, reported by `build`
+    rejected: Vec<Inconsistency>,
+}
+
+impl ServerBuilder {
+    /// Starts a builder advertising every capability the crate supports.
+    pub fn new(name: &str, version: &str) -> Self {
+        ServerBuilder {
+            core: ServerCore::new(name, version),
+            allow_inconsistent: false,
+            rejected: Vec::new(),
+        }
+    }
+
+    /// Replaces the advertised capabilities; `initialize` echoes exactly these.
+    pub fn capabilities(mut self, capabilities: ServerCapabilities) -> Self {
+        self.core.capabilities = capabilities;
+        self
+    }
+
+    pub fn tool<F>(mut self, tool: Tool, handler: F) -> Self
+    where
+        F: Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync + 'static,
+    {
+        self.core.register_tool(tool, handler);
+        self
+    }
+
+    /// Registers a resource; one whose URI has no scheme makes `build` fail.
+    pub fn resource<F>(mut self, resource: Resource, reader: F) -> Self
+    where
+        F: Fn(&RequestContext) -> HandlerResult<ResourceContents> + Send + Sync + 'static,
+    {
+        let uri = resource.uri.clone();
+        if self.core.add_resource(resource, reader).is_err() {
+            self.rejected.push(Inconsistency::InvalidResourceUri(uri));
+        }
+        self
+    }
+
+    /// Registers a resource template; like `resource`, one whose URI has no
+    /// scheme makes `build` fail.
+    pub fn resource_template<F>(mut self, template: ResourceTemplate, reader: F) -> Self
+    where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<Vec<ResourceContents>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let uri = template.uri_template.clone();
+        if self.core.add_resource_template(template, reader).is_err() {
+            self.rejected.push(Inconsistency::InvalidResourceUri(uri));
+        }
+        self
+    }
+
+    pub fn prompt<F, R>(mut self, prompt: Prompt, render: F) -> Self
+    where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<R>
+            + Send
+            + Sync
+            + 'static,
+        R: Into<PromptResult>,
+    {
+        self.core.add_prompt(prompt, render);
+        self
+    }
+
+    /// Registers a custom method; one named like a built-in one, such as
+    /// `tools/call`, counts as serving that capability.
+    pub fn method<F>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(Option<Value>, &RequestContext) -> HandlerResult<Value> + Send + Sync + 'static,
+    {
+        self.core.register_method(name, handler);
+        self
+    }
+
+    /// Builds the server even if it is inconsistent.
+    pub fn allow_inconsistent(mut self) -> Self {
+        self.allow_inconsistent = true;
+        self
+    }
+
+    pub fn build(self) -> Result<Server, BuildError> {
+        let mut inconsistencies = self.rejected;
+        inconsistencies.extend(self.core.inconsistencies());
+        if inconsistencies.is_empty() || self.allow_inconsistent {
+            Ok(Server::from_core(self.core))
+        } else {
+            Err(BuildError { inconsistencies })
+        }
+    }
+}
+
+impl ServerCore {
+    /// Checks the server as configured now, including anything registered
+    /// after it was built: the inconsistencies `ServerBuilder::build` rejects,
+    /// and required prompt arguments without a completion provider.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = self
+            .inconsistencies()
+            .into_iter()
+            .map(Diagnostic::Inconsistency)
+            .collect();
+        for entry in &self.prompts {
+            for argument in &entry.prompt.arguments {
+                if argument.required && !entry.completions.contains_key(&argument.name) {
+                    diagnostics.push(Diagnostic::MissingCompletion {
+                        prompt: entry.prompt.name.clone(),
+                        argument: argument.name.clone(),
+                    });
+                }
+            }
+        }
+        diagnostics
+    }
+
+    fn inconsistencies(&self) -> Vec<Inconsistency> {
+        let checks = [
+            (
+                Capability::Tools,
+                "tools",
+                !self.tools.is_empty(),
+                "tools/call",
+            ),
+            (
+                Capability::Resources,
+                "resources",
+                !self.resources.is_empty() || !self.templates.is_empty(),
+                "resources/read",
+            ),
+            (
+                Capability::Prompts,
+                "prompts",
+                !self.prompts.is_empty(),
+                "prompts/get",
+            ),
+        ];
+        checks
+            .into_iter()
+            .filter_map(|(capability, name, registered, method)| {
+                match (self.capabilities.advertises(capability), registered) {
+                    (true, false) if !self.methods.contains_key(method) => {
+                        Some(Inconsistency::UnservedCapability(name))
+                    }
+                    (false, true) => Some(Inconsistency::UnadvertisedCapability(name)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::initialize_params;
+    use crate::{PromptsCapability, ResourcesCapability, ToolsCapability};
+
+    fn echo_tool() -> Tool {
+        Tool {
+            name: "echo".to_string(),
+            description: None,
+            input_schema: serde_json::json!({ "type": "object" }),
+            annotations: None,
+            meta: None,
+        }
+    }
+
+    fn tools_only() -> ServerCapabilities {
+        ServerCapabilities {
+            tools: Some(ToolsCapability::default()),
+            ..ServerCapabilities::default()
+        }
+    }
+
+    #[test]
+    fn test_build_checks_consistency() {
+        let built = ServerBuilder::new("test-server", "1.0.0")
+            .capabilities(tools_only())
+            .tool(echo_tool(), |_, _| Ok(vec![]))
+            .build();
+        assert!(built.is_ok());
+
+        // A custom `tools/call` serves the capability without registered tools
+        let built = ServerBuilder::new("test-server", "1.0.0")
+            .capabilities(tools_only())
+            .method("tools/call", |_, _| Ok(Value::Null))
+            .build();
+        assert!(built.is_ok());
+
+        let err = ServerBuilder::new("test-server", "1.0.0")
+            .capabilities(tools_only())
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.inconsistencies,
+            [Inconsistency::UnservedCapability("tools")]
+        );
+
+        let err = ServerBuilder::new("test-server", "1.0.0")
+            .capabilities(ServerCapabilities {
+                prompts: Some(PromptsCapability::default()),
+                resources: Some(ResourcesCapability::default()),
+                ..ServerCapabilities::default()
+            })
+            .tool(echo_tool(), |_, _| Ok(vec![]))
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.inconsistencies,
+            [
+                Inconsistency::UnadvertisedCapability("tools"),
+                Inconsistency::UnservedCapability("resources"),
+                Inconsistency::UnservedCapability("prompts"),
+            ]
+        );
+        assert!(err.to_string().contains("`tools` are registered"));
+    }
+
+    #[test]
+    fn test_initialize_echoes_built_capabilities() {
+        let server = ServerBuilder::new("test-server", "1.0.0")
+            .capabilities(tools_only())
+            .tool(echo_tool(), |_, _| Ok(vec![]))
+            .build()
+            .unwrap();
+        let response = server
+            .handle_value(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": initialize_params(serde_json::json!({}))
+            }))
+            .unwrap()
+            .unwrap();
+        let capabilities = &response["result"]["capabilities"];
+        assert!(capabilities.get("tools").is_some());
+        assert!(capabilities.get("prompts").is_none());
+        assert!(capabilities.get("resources").is_none());
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut server = Server::with_capabilities("test-server", "1.0.0", tools_only());
+        assert_eq!(
+            server.diagnostics(),
+            [Diagnostic::Inconsistency(
+                Inconsistency::UnservedCapability("tools")
+            )]
+        );
+
+        server.register_tool(echo_tool(), |_, _| Ok(vec![]));
+        assert!(server.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_allow_inconsistent() {
+        let server = ServerBuilder::new("test-server", "1.0.0")
+            .capabilities(ServerCapabilities::default())
+            .tool(echo_tool(), |_, _| Ok(vec![]))
+            .allow_inconsistent()
+            .build()
+            .unwrap();
+        assert_eq!(server.export_config().tools.len(), 1);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    ErrorResponse, McpError, Notification, ProgressToken, Request, RequestId, Response,
+    ResponseCallback,
+};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+type ProgressCallback = Box<dyn FnMut(&Notification) + Send>;
+
+/// The client half of a connection. Like `Session` it doesn't own a transport:
+/// the caller sends the requests it builds and feeds every message from the
+/// server to `handle_message`. Responses and progress notifications are routed
+/// to the callbacks of the request they belong to; all other notifications are
+/// queued, or delivered to the stream returned by `notifications`.
+pub struct Client {
+    state: Mutex<ClientState>,
+}
+
+struct ClientState {
+    next_request_id: i64,
+    pending_requests: HashMap<RequestId, ResponseCallback>,
+    progress: HashMap<ProgressToken, ProgressCallback>,
+    notifications: VecDeque<Notification>,
+    #[cfg(feature = "async")]
+    subscriber: Option<tokio::sync::mpsc::UnboundedSender<Notification>>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Client {
+            state: Mutex::new(ClientState {
+                next_request_id: 1,
+                pending_requests: HashMap::new(),
+                progress: HashMap::new(),
+                notifications: VecDeque::new(),
+                #[cfg(feature = "async")]
+                subscriber: None,
+            }),
+        }
+    }
+
+    /// Builds a request for the caller to send; `on_response` runs when the
+    /// server answers it.
+    pub fn request<F>(&self, method: &str, params: Option<Value>, on_response: F) -> Request
+    where
+        F: FnOnce(Result<Value, ErrorResponse>) + Send + 'static,
+    {
+        let mut state = self.state.lock().unwrap();
+        let id = RequestId::Number(state.next_request_id);
+        state.next_request_id += 1;
+        state
+            .pending_requests
+            .insert(id.clone(), Box::new(on_response));
+        Request::new(id, method, params)
+    }
+
+    /// Like `request`, but asks the server for progress using the request id as
+    /// the progress token. `on_progress` receives each `notifications/progress`
+    /// for the request until it's answered.
+    pub fn request_with_progress<P, F>(
+        &self,
+        method: &str,
+        params: Option<Value>,
+        on_progress: P,
+        on_response: F,
+    ) -> Request
+    where
+        P: FnMut(&Notification) + Send + 'static,
+        F: FnOnce(Result<Value, ErrorResponse>) + Send + 'static,
+    {
+        let mut request = self.request(method, params, on_response);
+        let params = request
+            .params
+            .get_or_insert_with(|| Value::Object(serde_json::Map::new()));
+        if let Some(params) = params.as_object_mut() {
+            let meta = params
+                .entry("_meta")
+                .or_insert_with(|| Value::Object(serde_json::Map::new()));
+            meta["progressToken"] = serde_json::json!(request.id);
+        }
+        self.state
+            .lock()
+            .unwrap()
+            .progress
+            .insert(request.id.clone(), Box::new(on_progress));
+        request
+    }
+
+    /// Routes a message received from the server.
+    pub fn handle_message(&self, message: &str) -> Result<(), McpError> {
+        let parsed: Value = serde_json::from_str(message)?;
+        if parsed.get("method").is_none() {
+            let response: Response = serde_json::from_value(parsed)?;
+            let callback = {
+                let mut state = self.state.lock().unwrap();
+                state.progress.remove(&response.id);
+                state.pending_requests.remove(&response.id)
+            };
+            // Callbacks run without the lock so they may issue further requests
+            if let Some(callback) = callback {
+                match response.error {
+                    Some(error) => callback(Err(error)),
+                    None => callback(Ok(response.result.unwrap_or(Value::Null))),
+                }
+            }
+            return Ok(());
+        }
+        if parsed.get("id").is_some() {
+            // Server-initiated requests aren't supported by the client yet
+            return Ok(());
+        }
+
+        let notification: Notification = serde_json::from_value(parsed)?;
+        let mut state = self.state.lock().unwrap();
+        let token = (notification.method == "notifications/progress")
+            .then(|| notification.params.as_ref()?.get("progressToken").cloned())
+            .flatten()
+            .and_then(|token| serde_json::from_value::<ProgressToken>(token).ok());
+        if let Some(on_progress) = token.and_then(|token| state.progress.get_mut(&token)) {
+            on_progress(&notification);
+            return Ok(());
+        }
+        state.deliver(notification);
+        Ok(())
+    }
+
+    /// Removes and returns the notifications queued while no stream was attached.
+    pub fn drain_notifications(&self) -> Vec<Notification> {
+        self.state.lock().unwrap().notifications.drain(..).collect()
+    }
+
+    /// Streams the server's notifications, starting with any already queued.
+    /// Only the most recently returned stream receives notifications; the
+    /// stream ends when the client is dropped.
+    #[cfg(feature = "async")]
+    pub fn notifications(&self) -> NotificationStream {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut state = self.state.lock().unwrap();
+        for notification in state.notifications.drain(..) {
+            let _ = sender.send(notification);
+        }
+        state.subscriber = Some(sender);
+        NotificationStream { receiver }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientState {
+    fn deliver(&mut self, notification: Notification) {
+        #[cfg(feature = "async")]
+        let notification = match &self.subscriber {
+            Some(subscriber) => match subscriber.send(notification) {
+                Ok(()) => return,
+                // The stream was dropped; fall back to queueing
+                Err(err) => {
+                    self.subscriber = None;
+                    err.0
+                }
+            },
+            None => notification,
+        };
+        self.notifications.push_back(notification);
+    }
+}
+
+/// Server notifications not claimed by an in-flight request; see
+/// `Client::notifications`.
+#[cfg(feature = "async")]
+pub struct NotificationStream {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<Notification>,
+}
+
+#[cfg(feature = "async")]
+impl futures_core::Stream for NotificationStream {
+    type Item = Notification;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Notification>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use super::*;
+    use crate::{LoggingLevel, Server};
+    use futures_util::StreamExt;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_notification_stream() {
+        let server = Server::new("test-server", "1.0.0");
+        let client = Client::new();
+        let mut stream = client.notifications();
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&progress);
+        let request = client.request_with_progress(
+            "tools/call",
+            None,
+            move |notification| seen.lock().unwrap().push(notification.params.clone()),
+            |_| {},
+        );
+        assert_eq!(
+            request.params.as_ref().unwrap()["_meta"]["progressToken"],
+            1
+        );
+
+        let progress_notification = Notification::progress(request.id.clone(), 0.5, None, None);
+        client
+            .handle_message(&serde_json::to_string(&progress_notification).unwrap())
+            .unwrap();
+        server.log(LoggingLevel::Info, Some("test"), "hello");
+        for notification in server.drain_notifications() {
+            client
+                .handle_message(&serde_json::to_string(&notification).unwrap())
+                .unwrap();
+        }
+
+        // Progress went to the request's callback; the log message to the stream
+        assert_eq!(progress.lock().unwrap().len(), 1);
+        let notification = stream.next().await.unwrap();
+        assert_eq!(notification.method(), "notifications/message");
+        assert_eq!(notification.params().unwrap()["data"], "hello");
+
+        drop(client);
+        assert!(stream.next().await.is_none());
+    }
+}
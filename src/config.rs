@@ -0,0 +1,275 @@
+use crate::{
+    normalize_resource, normalize_uri, prompt_renderer, Content, HandlerResult, McpError, Prompt,
+    PromptResult, RegisteredPrompt, RegisteredResource, RegisteredTool, RequestContext, Resource,
+    ResourceContents, ResourceSource, Server, ServerCapabilities, ServerCore, Tool,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Declarative description of a server: its identity, capabilities, and the
+/// tools, resources, and prompts it exposes. Handlers are not part of the
+/// config and are bound by name once the server is built.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub capabilities: ServerCapabilities,
+    #[serde(default)]
+    pub tools: Vec<Tool>,
+    #[serde(default)]
+    pub resources: Vec<Resource>,
+    #[serde(default)]
+    pub prompts: Vec<Prompt>,
+}
+
+impl ServerConfig {
+    pub fn from_json(value: Value) -> Result<Self, McpError> {
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+impl ServerCore {
+    /// Builds a core from `config`. Every descriptor starts with a handler that
+    /// fails until a real one is attached with `bind_tool`, `bind_resource`, or
+    /// `bind_prompt`. Fails if a resource URI has no scheme.
+    pub fn from_config(config: ServerConfig) -> Result<Self, McpError> {
+        let mut server =
+            ServerCore::with_capabilities(&config.name, &config.version, config.capabilities);
+        for tool in config.tools {
+            server.register_unbound_tool(tool);
+        }
+        for resource in config.resources {
+            server.add_unbound_resource(resource)?;
+        }
+        for prompt in config.prompts {
+            server.add_unbound_prompt(prompt);
+        }
+        Ok(server)
+    }
+
+    /// Registers several tools at once, to be given handlers with `bind_tool`.
+    /// Nothing is registered if a name repeats, within `tools` or with a tool
+    /// already registered; the error names the first collision.
+    pub fn register_tools(
+        &mut self,
+        tools: impl IntoIterator<Item = Tool>,
+    ) -> Result<(), McpError> {
+        let tools: Vec<Tool> = tools.into_iter().collect();
+        let registered = self.tools.iter().map(|entry| entry.tool.name.as_str());
+        check_unique(
+            "tool",
+            registered,
+            tools.iter().map(|tool| tool.name.as_str()),
+        )?;
+        for tool in tools {
+            self.register_unbound_tool(tool);
+        }
+        Ok(())
+    }
+
+    /// Like `register_tools`, for resources identified by URI and to be given
+    /// readers with `bind_resource`.
+    pub fn add_resources(
+        &mut self,
+        resources: impl IntoIterator<Item = Resource>,
+    ) -> Result<(), McpError> {
+        // Normalized first, so URIs differing only trivially count as repeats
+        let resources = resources
+            .into_iter()
+            .map(normalize_resource)
+            .collect::<Result<Vec<Resource>, McpError>>()?;
+        let registered = self
+            .resources
+            .iter()
+            .map(|entry| entry.resource.uri.as_str());
+        let added = resources.iter().map(|resource| resource.uri.as_str());
+        check_unique("resource", registered, added)?;
+        for resource in resources {
+            self.add_unbound_resource(resource)?;
+        }
+        Ok(())
+    }
+
+    /// Like `register_tools`, for prompts to be given renderers with `bind_prompt`.
+    pub fn add_prompts(
+        &mut self,
+        prompts: impl IntoIterator<Item = Prompt>,
+    ) -> Result<(), McpError> {
+        let prompts: Vec<Prompt> = prompts.into_iter().collect();
+        let registered = self.prompts.iter().map(|entry| entry.prompt.name.as_str());
+        check_unique(
+            "prompt",
+            registered,
+            prompts.iter().map(|p| p.name.as_str()),
+        )?;
+        for prompt in prompts {
+            self.add_unbound_prompt(prompt);
+        }
+        Ok(())
+    }
+
+    fn register_unbound_tool(&mut self, tool: Tool) {
+        let message = format!("no handler bound for tool `{}`", tool.name);
+        self.register_tool(tool, move |_, _| Err(message.clone().into()));
+    }
+
+    fn add_unbound_resource(&mut self, resource: Resource) -> Result<(), McpError> {
+        let message = format!("no reader bound for resource `{}`", resource.uri);
+        self.add_resource(resource, move |_| Err(message.clone().into()))
+    }
+
+    fn add_unbound_prompt(&mut self, prompt: Prompt) {
+        let message = format!("no renderer bound for prompt `{}`", prompt.name);
+        self.add_prompt(prompt, move |_, _| {
+            Err::<PromptResult, _>(message.clone().into())
+        });
+    }
+
+    /// Dumps the server's identity, capabilities, and descriptors.
+    pub fn export_config(&self) -> ServerConfig {
+        ServerConfig {
+            name: self.implementation.name.clone(),
+            version: self.implementation.version.clone(),
+            capabilities: self.capabilities.clone(),
+            tools: self.tools.iter().map(|entry| entry.tool.clone()).collect(),
+            resources: self
+                .resources
+                .iter()
+                .map(|entry| entry.resource.clone())
+                .collect(),
+            prompts: self
+                .prompts
+                .iter()
+                .map(|entry| entry.prompt.clone())
+                .collect(),
+        }
+    }
+
+    /// Attaches `handler` to the already-registered tool `name`.
+    pub fn bind_tool<F>(&mut self, name: &str, handler: F) -> Result<(), McpError>
+    where
+        F: Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync + 'static,
+    {
+        let entry: &mut RegisteredTool = self
+            .tools
+            .iter_mut()
+            .find(|entry| entry.tool.name == name)
+            .ok_or_else(|| McpError::Config(format!("no tool named `{name}` is registered")))?;
+        entry.handler = Arc::new(handler);
+        Ok(())
+    }
+
+    /// Attaches `reader` to the already-registered resource `uri`.
+    pub fn bind_resource<F>(&mut self, uri: &str, reader: F) -> Result<(), McpError>
+    where
+        F: Fn(&RequestContext) -> HandlerResult<ResourceContents> + Send + Sync + 'static,
+    {
+        let normalized = normalize_uri(uri).unwrap_or_else(|| uri.to_string());
+        let entry: &mut RegisteredResource = self
+            .resources
+            .iter_mut()
+            .find(|entry| entry.resource.uri == normalized)
+            .ok_or_else(|| McpError::Config(format!("no resource `{uri}` is registered")))?;
+        entry.reader = ResourceSource::Contents(Arc::new(reader));
+        Ok(())
+    }
+
+    /// Attaches `render` to the already-registered prompt `name`.
+    pub fn bind_prompt<F, R>(&mut self, name: &str, render: F) -> Result<(), McpError>
+    where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<R>
+            + Send
+            + Sync
+            + 'static,
+        R: Into<PromptResult>,
+    {
+        let entry: &mut RegisteredPrompt = self
+            .prompts
+            .iter_mut()
+            .find(|entry| entry.prompt.name == name)
+            .ok_or_else(|| McpError::Config(format!("no prompt named `{name}` is registered")))?;
+        entry.render = prompt_renderer(render);
+        Ok(())
+    }
+}
+
+// Fails on the first key of `added` that is already `registered` or repeats
+fn check_unique<'a>(
+    kind: &str,
+    registered: impl Iterator<Item = &'a str>,
+    added: impl Iterator<Item = &'a str>,
+) -> Result<(), McpError> {
+    let mut seen: HashSet<&str> = registered.collect();
+    for key in added {
+        if !seen.insert(key) {
+            return Err(McpError::Config(format!(
+                "{kind} `{key}` is registered more than once"
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl Server {
+    /// Builds a server from `config`; see `ServerCore::from_config`.
+    pub fn from_config(config: ServerConfig) -> Result<Self, McpError> {
+        ServerCore::from_config(config).map(Server::from_core)
+    }
+
+    /// Registers several tools at once; see `ServerCore::register_tools`.
+    pub fn register_tools(
+        &mut self,
+        tools: impl IntoIterator<Item = Tool>,
+    ) -> Result<(), McpError> {
+        self.core_mut().register_tools(tools)
+    }
+
+    /// Registers several resources at once; see `ServerCore::add_resources`.
+    pub fn add_resources(
+        &mut self,
+        resources: impl IntoIterator<Item = Resource>,
+    ) -> Result<(), McpError> {
+        self.core_mut().add_resources(resources)?;
+        self.resources_changed();
+        Ok(())
+    }
+
+    /// Registers several prompts at once; see `ServerCore::add_prompts`.
+    pub fn add_prompts(
+        &mut self,
+        prompts: impl IntoIterator<Item = Prompt>,
+    ) -> Result<(), McpError> {
+        self.core_mut().add_prompts(prompts)
+    }
+
+    /// Attaches `handler` to the already-registered tool `name`.
+    pub fn bind_tool<F>(&mut self, name: &str, handler: F) -> Result<(), McpError>
+    where
+        F: Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync + 'static,
+    {
+        self.core_mut().bind_tool(name, handler)
+    }
+
+    /// Attaches `reader` to the already-registered resource `uri`.
+    pub fn bind_resource<F>(&mut self, uri: &str, reader: F) -> Result<(), McpError>
+    where
+        F: Fn(&RequestContext) -> HandlerResult<ResourceContents> + Send + Sync + 'static,
+    {
+        self.core_mut().bind_resource(uri, reader)
+    }
+
+    /// Attaches `render` to the already-registered prompt `name`.
+    pub fn bind_prompt<F, R>(&mut self, name: &str, render: F) -> Result<(), McpError>
+    where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<R>
+            + Send
+            + Sync
+            + 'static,
+        R: Into<PromptResult>,
+    {
+        self.core_mut().bind_prompt(name, render)
+    }
+}
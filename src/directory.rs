@@ -0,0 +1,291 @@
+use crate::{
+    percent_encode, HandlerResult, McpError, Resource, ResourceContents, ResourceTemplate, Server,
+    ServerCore, BASE64,
+};
+use base64::Engine;
+use std::path::{Component, Path, PathBuf};
+
+/// Which entries `add_directory_with_options` registers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirectoryOptions {
+    /// Registers files and directories whose names start with `.`.
+    pub include_hidden: bool,
+    /// Follows symbolic links instead of skipping them.
+    pub follow_symlinks: bool,
+}
+
+// MIME types by file extension, and whether they are served as text
+const MIME_TYPES: &[(&str, &str, bool)] = &[
+    ("txt", "text/plain", true),
+    ("md", "text/markdown", true),
+    ("html", "text/html", true),
+    ("css", "text/css", true),
+    ("csv", "text/csv", true),
+    ("js", "text/javascript", true),
+    ("rs", "text/x-rust", true),
+    ("toml", "application/toml", true),
+    ("yaml", "application/yaml", true),
+    ("yml", "application/yaml", true),
+    ("json", "application/json", true),
+    ("xml", "application/xml", true),
+    ("svg", "image/svg+xml", true),
+    ("png", "image/png", false),
+    ("jpg", "image/jpeg", false),
+    ("jpeg", "image/jpeg", false),
+    ("gif", "image/gif", false),
+    ("pdf", "application/pdf", false),
+];
+
+// Guesses from the extension, falling back to opaque bytes
+fn mime_type(path: &Path) -> (&'static str, bool) {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    MIME_TYPES
+        .iter()
+        .find(|(known, _, _)| Some(*known) == extension.as_deref())
+        .map_or(("application/octet-stream", false), |&(_, mime, text)| {
+            (mime, text)
+        })
+}
+
+fn read_file(path: &Path, uri: String) -> HandlerResult<ResourceContents> {
+    let (mime, text) = mime_type(path);
+    let mime_type = Some(mime.to_string());
+    let contents = if text {
+        ResourceContents::Text {
+            uri,
+            mime_type,
+            text: std::fs::read_to_string(path)?,
+        }
+    } else {
+        ResourceContents::Blob {
+            uri,
+            mime_type,
+            blob: BASE64.encode(std::fs::read(path)?),
+        }
+    };
+    Ok(contents)
+}
+
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+impl ServerCore {
+    /// Registers every file under `path` as a resource at `uri_prefix` followed
+    /// by its relative path, say `file:///docs/guide/intro.md`, with a MIME type
+    /// guessed from its extension and its size, plus a `{uri_prefix}/{name}`
+    /// template reading files added to the top of the directory later. Hidden
+    /// entries and symbolic links are skipped.
+    pub fn add_directory(
+        &mut self,
+        uri_prefix: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<(), McpError> {
+        self.add_directory_with_options(uri_prefix, path, DirectoryOptions::default())
+    }
+
+    /// Like `add_directory`, with `options` choosing which entries to include.
+    pub fn add_directory_with_options(
+        &mut self,
+        uri_prefix: &str,
+        path: impl AsRef<Path>,
+        options: DirectoryOptions,
+    ) -> Result<(), McpError> {
+        let root = path.as_ref().to_path_buf();
+        let uri_prefix = uri_prefix.trim_end_matches('/');
+        let mut files = Vec::new();
+        collect_files(&root, uri_prefix, options, &mut files)?;
+        files.sort();
+        for (uri, file) in files {
+            let (mime, _) = mime_type(&file);
+            let resource = Resource {
+                uri: uri.clone(),
+                name: file
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                description: None,
+                mime_type: Some(mime.to_string()),
+                size: Some(std::fs::metadata(&file)?.len()),
+                annotations: None,
+            };
+            self.add_resource(resource, move |_| read_file(&file, uri.clone()))?;
+        }
+
+        let template = ResourceTemplate {
+            uri_template: format!("{uri_prefix}/{{name}}"),
+            name: root.display().to_string(),
+            description: None,
+            mime_type: None,
+        };
+        let prefix = uri_prefix.to_string();
+        self.add_resource_template(template, move |variables, _| {
+            let name = &variables["name"];
+            // Only a plain file name may be joined onto the root: an absolute
+            // path would replace it and `..` would climb out of it
+            let mut components = Path::new(name).components();
+            let single = matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(_)), None)
+            );
+            if !single || (is_hidden(name) && !options.include_hidden) {
+                return Err(format!("no file `{name}` in this directory").into());
+            }
+            let file = root.join(name);
+            let metadata = std::fs::symlink_metadata(&file)?;
+            if (metadata.is_symlink() && !options.follow_symlinks) || file.is_dir() {
+                return Err(format!("no file `{name}` in this directory").into());
+            }
+            let uri = format!("{prefix}/{}", percent_encode(name));
+            Ok(vec![read_file(&file, uri)?])
+        })
+    }
+}
+
+// Gathers the files below `dir` with their URIs, each path segment escaped
+fn collect_files(
+    dir: &Path,
+    uri_prefix: &str,
+    options: DirectoryOptions,
+    files: &mut Vec<(String, PathBuf)>,
+) -> Result<(), McpError> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_hidden(&name) && !options.include_hidden {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() && !options.follow_symlinks {
+            continue;
+        }
+        let uri = format!("{uri_prefix}/{}", percent_encode(&name));
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, &uri, options, files)?;
+        } else if path.is_file() {
+            files.push((uri, path));
+        }
+    }
+    Ok(())
+}
+
+impl Server {
+    /// Registers the files under `path` as resources; see `ServerCore::add_directory`.
+    pub fn add_directory(
+        &mut self,
+        uri_prefix: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<(), McpError> {
+        self.core_mut().add_directory(uri_prefix, path)?;
+        self.resources_changed();
+        Ok(())
+    }
+
+    /// Like `add_directory`, with `options` choosing which entries to include.
+    pub fn add_directory_with_options(
+        &mut self,
+        uri_prefix: &str,
+        path: impl AsRef<Path>,
+        options: DirectoryOptions,
+    ) -> Result<(), McpError> {
+        self.core_mut()
+            .add_directory_with_options(uri_prefix, path, options)?;
+        self.resources_changed();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::initialized;
+    use serde_json::Value;
+
+    #[test]
+    fn test_add_directory() {
+        let dir = std::env::temp_dir().join(format!("mcp_rs-directory-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.md"), "# Notes").unwrap();
+        std::fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        std::fs::write(dir.join(".secret"), "hidden").unwrap();
+
+        let mut server = Server::new("test-server", "1.0.0");
+        server.add_directory("file:///site/", &dir).unwrap();
+        let server = initialized(server);
+
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "resources/list" });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(
+            response["result"]["resources"],
+            serde_json::json!([
+                { "uri": "file:///site/logo.png", "name": "logo.png", "mimeType": "image/png", "size": 4 },
+                { "uri": "file:///site/notes.md", "name": "notes.md", "mimeType": "text/markdown", "size": 7 }
+            ])
+        );
+
+        // Files created after registration are reachable through the template
+        std::fs::write(dir.join("later.txt"), "new").unwrap();
+        let read = |uri: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "resources/read",
+                "params": { "uri": uri }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+        assert_eq!(
+            read("file:///site/later.txt")["result"]["contents"][0]["text"],
+            "new"
+        );
+        assert!(read("file:///site/.secret").get("error").is_some());
+        let blob = &read("file:///site/logo.png")["result"]["contents"][0]["blob"];
+        assert_eq!(blob, &Value::from("iVBORw=="));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_directory_template_stays_in_root() {
+        let base = std::env::temp_dir().join(format!("mcp_rs-escape-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let dir = base.join("site");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(base.join("outside.txt"), "secret").unwrap();
+
+        let mut server = Server::new("test-server", "1.0.0");
+        let options = DirectoryOptions {
+            include_hidden: true,
+            ..DirectoryOptions::default()
+        };
+        server
+            .add_directory_with_options("file:///site", &dir, options)
+            .unwrap();
+        let server = initialized(server);
+
+        let outside = base.join("outside.txt");
+        let absolute = crate::percent_encode(&outside.to_string_lossy());
+        for uri in [
+            format!("file:///site/{absolute}"),
+            "file:///site/%2Fetc%2Fhostname".to_string(),
+            "file:///site/..%2Foutside.txt".to_string(),
+            "file:///site/..".to_string(),
+        ] {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "resources/read",
+                "params": { "uri": uri }
+            });
+            let response = server.handle_value(request).unwrap().unwrap();
+            assert!(response.get("error").is_some(), "{uri}: {response}");
+        }
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+
+/// JSON-RPC error codes used in error responses: the standard ones plus those
+/// defined by MCP or this crate in the implementation-defined range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    ParseError,
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    InternalError,
+    /// Generic server error, e.g. an unauthorized request or an exceeded limit.
+    ServerError,
+    RequestTimeout,
+    ResourceNotFound,
+    /// A request other than `initialize` or `ping` sent before the handshake
+    /// completed.
+    NotInitialized,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 9] = [
+        ErrorCode::ParseError,
+        ErrorCode::InvalidRequest,
+        ErrorCode::MethodNotFound,
+        ErrorCode::InvalidParams,
+        ErrorCode::InternalError,
+        ErrorCode::ServerError,
+        ErrorCode::RequestTimeout,
+        ErrorCode::ResourceNotFound,
+        ErrorCode::NotInitialized,
+    ];
+
+    pub fn as_i32(self) -> i32 {
+        match self {
+            ErrorCode::ParseError => -32700,
+            ErrorCode::InvalidRequest => -32600,
+            ErrorCode::MethodNotFound => -32601,
+            ErrorCode::InvalidParams => -32602,
+            ErrorCode::InternalError => -32603,
+            ErrorCode::ServerError => -32000,
+            ErrorCode::RequestTimeout => -32001,
+            ErrorCode::ResourceNotFound => -32002,
+            ErrorCode::NotInitialized => -32003,
+        }
+    }
+
+    /// The variant for `code`, or `None` if it isn't one this crate knows.
+    pub fn from_i32(code: i32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|variant| variant.as_i32() == code)
+    }
+}
+
+/// Errors surfaced by the server outside of JSON-RPC error responses, such as
+/// malformed input or a failing transport.
+#[derive(Debug)]
+pub enum McpError {
+    Json(serde_json::Error),
+    Io(std::io::Error),
+    /// The server was configured inconsistently, e.g. binding an unknown tool.
+    Config(String),
+}
+
+impl fmt::Display for McpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            McpError::Json(err) => write!(f, "invalid JSON-RPC message: {err}"),
+            McpError::Io(err) => write!(f, "transport error: {err}"),
+            McpError::Config(message) => write!(f, "invalid server configuration: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for McpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            McpError::Json(err) => Some(err),
+            McpError::Io(err) => Some(err),
+            McpError::Config(_) => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for McpError {
+    fn from(err: serde_json::Error) -> Self {
+        McpError::Json(err)
+    }
+}
+
+impl From<std::io::Error> for McpError {
+    fn from(err: std::io::Error) -> Self {
+        McpError::Io(err)
+    }
+}
+
+/// A way in which a server's advertised capabilities and its registered
+/// handlers disagree, or a registration couldn't be made. Capabilities are
+/// named as in `initialize`, e.g. `tools`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The capability is advertised but nothing can serve its requests.
+    UnservedCapability(&'static str),
+    /// Handlers are registered for a capability that isn't advertised, so
+    /// clients can never reach them.
+    UnadvertisedCapability(&'static str),
+    /// A resource or resource template was given to the builder under a URI
+    /// without a scheme, so it wasn't registered.
+    InvalidResourceUri(String),
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inconsistency::UnservedCapability(name) => {
+                write!(f, "`{name}` is advertised but nothing is registered for it")
+            }
+            Inconsistency::UnadvertisedCapability(name) => {
+                write!(
+                    f,
+                    "`{name}` are registered but the capability isn't advertised"
+                )
+            }
+            Inconsistency::InvalidResourceUri(uri) => {
+                write!(f, "resource URI `{uri}` has no scheme")
+            }
+        }
+    }
+}
+
+/// A warning about a live server, from `ServerCore::diagnostics`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    Inconsistency(Inconsistency),
+    /// A required prompt argument has no completion provider, so clients
+    /// can't suggest values for it.
+    MissingCompletion {
+        prompt: String,
+        argument: String,
+    },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Inconsistency(inconsistency) => write!(f, "{inconsistency}"),
+            Diagnostic::MissingCompletion { prompt, argument } => write!(
+                f,
+                "prompt `{prompt}` requires argument `{argument}` but has no completion provider for it"
+            ),
+        }
+    }
+}
+
+/// Returned by `ServerBuilder::build` for a misconfigured server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildError {
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "inconsistent server: ")?;
+        for (i, inconsistency) in self.inconsistencies.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{inconsistency}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_code_round_trip() {
+        let codes: Vec<i32> = ErrorCode::ALL.iter().map(|code| code.as_i32()).collect();
+        assert_eq!(
+            codes,
+            [-32700, -32600, -32601, -32602, -32603, -32000, -32001, -32002, -32003]
+        );
+        for code in ErrorCode::ALL {
+            assert_eq!(ErrorCode::from_i32(code.as_i32()), Some(code));
+        }
+        assert_eq!(ErrorCode::from_i32(-31999), None);
+    }
+}
@@ -0,0 +1,440 @@
+use crate::transport::{Direction, MessageSink};
+#[cfg(feature = "async")]
+use crate::{ByteStream, McpError};
+use crate::{ErrorCode, ErrorResponse, RequestId, Response, Server};
+use serde_json::Value;
+
+/// An HTTP request as seen by the transport. Decoupled from any particular
+/// HTTP framework: adapters convert their own request type into this one.
+#[derive(Debug, Clone, Default)]
+pub struct HttpRequest {
+    pub method: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpRequest {
+    /// A `POST` carrying `body`, the only kind of request the transport serves.
+    pub fn post(body: impl Into<String>) -> Self {
+        HttpRequest {
+            method: "POST".to_string(),
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The first value of header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    fn json(status: u16, body: String) -> Self {
+        HttpResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Something that answers HTTP requests: the transport itself, or a
+/// middleware wrapping it.
+pub trait HttpHandler: Send + Sync {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse;
+}
+
+/// Serves JSON-RPC over HTTP: each `POST` body is one message, answered with
+/// its response, or `202 Accepted` with no body for notifications and
+/// responses to server-initiated requests.
+pub struct HttpTransport {
+    server: Server,
+}
+
+impl HttpTransport {
+    pub fn new(server: Server) -> Self {
+        HttpTransport { server }
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Answers `resources/read` for streamed resources with a reference to
+    /// `path?uri=...` rather than the body; the adapter serves `GET`s on
+    /// `path` with `resource_body` or `stream_resource` for the decoded `uri`.
+    #[cfg(feature = "async")]
+    pub fn stream_resources_at(self, path: &str) -> Self {
+        self.server.set_stream_endpoint(Some(path.to_string()));
+        self
+    }
+
+    /// The body of the streamed resource at `uri`, for an adapter to hand to
+    /// its HTTP library as a streaming response body.
+    #[cfg(feature = "async")]
+    pub fn resource_body(&self, uri: &str) -> Result<ByteStream, McpError> {
+        self.server.open_resource_stream(uri)
+    }
+
+    /// Writes the body of the streamed resource at `uri` to `output` chunk
+    /// by chunk as it is produced, returning the number of bytes written.
+    #[cfg(feature = "async")]
+    pub async fn stream_resource<W>(&self, uri: &str, output: &mut W) -> Result<u64, McpError>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::AsyncWriteExt;
+
+        let mut stream = self.resource_body(uri)?;
+        let mut total = 0;
+        while let Some(chunk) =
+            std::future::poll_fn(|cx| futures_core::Stream::poll_next(stream.as_mut(), cx)).await
+        {
+            let chunk = chunk?;
+            output.write_all(&chunk).await?;
+            total += chunk.len() as u64;
+        }
+        output.flush().await?;
+        Ok(total)
+    }
+
+    /// Answers a scrape with `Session::metrics_prometheus`; the adapter serves
+    /// `GET`s on its metrics path, usually `/metrics`, by calling this.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: vec![(
+                "Content-Type".to_string(),
+                "text/plain; version=0.0.4".to_string(),
+            )],
+            body: self.server.metrics_prometheus(),
+        }
+    }
+}
+
+impl HttpHandler for HttpTransport {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        if !request.method.eq_ignore_ascii_case("POST") {
+            return HttpResponse {
+                status: 405,
+                headers: vec![("Allow".to_string(), "POST".to_string())],
+                body: String::new(),
+            };
+        }
+        match self.server.handle_message(&request.body) {
+            Ok(Some(response)) => HttpResponse::json(200, response),
+            Ok(None) => HttpResponse {
+                status: 202,
+                headers: Vec::new(),
+                body: String::new(),
+            },
+            Err(err) => HttpResponse {
+                status: 500,
+                headers: Vec::new(),
+                body: err.to_string(),
+            },
+        }
+    }
+}
+
+type TokenValidator = Box<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Requires a bearer token accepted by `validator` before passing a request
+/// on to `inner`. Anything else is answered with `401 Unauthorized` and a
+/// JSON-RPC error, without reaching the server.
+pub struct AuthMiddleware<H> {
+    inner: H,
+    validator: TokenValidator,
+}
+
+impl<H: HttpHandler> AuthMiddleware<H> {
+    pub fn new<F>(inner: H, validator: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        AuthMiddleware {
+            inner,
+            validator: Box::new(validator),
+        }
+    }
+}
+
+// The token of `Bearer` credentials; the scheme name is case-insensitive
+fn bearer_token(credentials: &str) -> Option<&str> {
+    let (scheme, token) = credentials.trim().split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("Bearer")
+        .then(|| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+impl<H: HttpHandler> HttpHandler for AuthMiddleware<H> {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        let token = request.header("Authorization").and_then(bearer_token);
+        if token.is_some_and(|token| (self.validator)(token)) {
+            return self.inner.handle(request);
+        }
+
+        // Echo the request id when there is one so the client can correlate
+        let id = serde_json::from_str::<Value>(&request.body)
+            .ok()
+            .and_then(|message| serde_json::from_value(message.get("id")?.clone()).ok())
+            .unwrap_or(RequestId::Null);
+        let response = Response::from_result(
+            id,
+            Err(ErrorResponse {
+                code: ErrorCode::ServerError.as_i32(),
+                message: "Unauthorized".to_string(),
+                data: None,
+            }),
+        );
+        let mut response =
+            HttpResponse::json(401, serde_json::to_string(&response).unwrap_or_default());
+        response
+            .headers
+            .push(("WWW-Authenticate".to_string(), "Bearer".to_string()));
+        response
+    }
+}
+
+/// Hands every request body and every non-empty response body passing
+/// through to `sink`, for debugging, before passing the request on to `inner`.
+/// `LoggingTransport` does the same for the stream transports.
+pub struct LoggingMiddleware<H> {
+    inner: H,
+    sink: MessageSink,
+}
+
+impl<H: HttpHandler> LoggingMiddleware<H> {
+    pub fn new<F>(inner: H, sink: F) -> Self
+    where
+        F: Fn(Direction, &str) + Send + Sync + 'static,
+    {
+        LoggingMiddleware {
+            inner,
+            sink: Box::new(sink),
+        }
+    }
+}
+
+impl<H: HttpHandler> HttpHandler for LoggingMiddleware<H> {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        (self.sink)(Direction::Inbound, &request.body);
+        let response = self.inner.handle(request);
+        if !response.body.is_empty() {
+            (self.sink)(Direction::Outbound, &response.body);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::initialized;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_bearer_auth() {
+        let transport = AuthMiddleware::new(
+            HttpTransport::new(Server::new("test-server", "1.0.0")),
+            |token| token == "s3cret",
+        );
+        let ping = r#"{"jsonrpc":"2.0","id":7,"method":"ping"}"#;
+
+        for request in [
+            HttpRequest::post(ping),
+            HttpRequest::post(ping).with_header("Authorization", "Bearer wrong"),
+        ] {
+            let response = transport.handle(&request);
+            assert_eq!(response.status, 401);
+            assert_eq!(response.header("WWW-Authenticate"), Some("Bearer"));
+            let body: Value = serde_json::from_str(&response.body).unwrap();
+            assert_eq!(body["error"]["code"], -32000);
+            assert_eq!(body["id"], 7);
+        }
+
+        for credentials in ["Bearer s3cret", "bearer s3cret", "BEARER  s3cret"] {
+            let request = HttpRequest::post(ping).with_header("authorization", credentials);
+            let response = transport.handle(&request);
+            assert_eq!(response.status, 200);
+            let body: Value = serde_json::from_str(&response.body).unwrap();
+            assert!(body["result"].is_object());
+        }
+
+        let request = HttpRequest::post(ping).with_header("Authorization", "Basic s3cret");
+        assert_eq!(transport.handle(&request).status, 401);
+    }
+
+    #[test]
+    fn test_logging_middleware() {
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&logged);
+        let transport = LoggingMiddleware::new(
+            HttpTransport::new(initialized(Server::new("test-server", "1.0.0"))),
+            move |direction, message: &str| {
+                sink.lock().unwrap().push((direction, message.to_string()));
+            },
+        );
+
+        let ping = r#"{"jsonrpc":"2.0","id":7,"method":"ping"}"#;
+        let response = transport.handle(&HttpRequest::post(ping));
+        let logged = logged.lock().unwrap();
+        assert_eq!(logged.len(), 2);
+        assert_eq!(logged[0], (Direction::Inbound, ping.to_string()));
+        assert_eq!(logged[1], (Direction::Outbound, response.body));
+    }
+
+    // Produces `remaining` bytes on demand, `CHUNK` at a time, without ever
+    // holding them all
+    #[cfg(feature = "async")]
+    struct Generated {
+        remaining: u64,
+    }
+
+    #[cfg(feature = "async")]
+    const CHUNK: usize = 64 * 1024;
+
+    #[cfg(feature = "async")]
+    impl futures_core::Stream for Generated {
+        type Item = Result<bytes::Bytes, std::io::Error>;
+
+        fn poll_next(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Self::Item>> {
+            let len = CHUNK.min(self.remaining as usize);
+            if len == 0 {
+                return std::task::Poll::Ready(None);
+            }
+            self.remaining -= len as u64;
+            std::task::Poll::Ready(Some(Ok(bytes::Bytes::from(vec![b'x'; len]))))
+        }
+    }
+
+    // Counts what is written, the writes, and the largest single write
+    #[cfg(feature = "async")]
+    #[derive(Default)]
+    struct Counting {
+        total: u64,
+        writes: usize,
+        largest_write: usize,
+    }
+
+    #[cfg(feature = "async")]
+    impl tokio::io::AsyncWrite for Counting {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            self.total += buf.len() as u64;
+            self.writes += 1;
+            self.largest_write = self.largest_write.max(buf.len());
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn streamed_server(size: u64) -> Server {
+        let mut server = Server::new("test-server", "1.0.0");
+        server
+            .add_streamed_resource(
+                crate::Resource {
+                    uri: "file:///big file.bin".to_string(),
+                    name: "big".to_string(),
+                    description: None,
+                    mime_type: Some("application/octet-stream".to_string()),
+                    size: None,
+                    annotations: None,
+                },
+                move |_| Ok(Generated { remaining: size }),
+            )
+            .unwrap();
+        initialized(server)
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_stream_large_resource() {
+        const SIZE: u64 = 64 * 1024 * 1024;
+        let transport = HttpTransport::new(streamed_server(SIZE)).stream_resources_at("/stream");
+
+        let read = r#"{"jsonrpc":"2.0","id":1,"method":"resources/read","params":{"uri":"file:///big file.bin"}}"#;
+        let response = transport.handle(&HttpRequest::post(read));
+        let body: Value = serde_json::from_str(&response.body).unwrap();
+        let contents = &body["result"]["contents"][0];
+        assert_eq!(
+            contents["_meta"]["streamUrl"],
+            "/stream?uri=file%3A%2F%2F%2Fbig%20file.bin"
+        );
+        assert!(contents.get("blob").is_none());
+
+        // The body goes out a chunk at a time as the stream produces it
+        let mut output = Counting::default();
+        let written = transport
+            .stream_resource("file:///big file.bin", &mut output)
+            .await
+            .unwrap();
+        assert_eq!(written, SIZE);
+        assert_eq!(output.total, SIZE);
+        assert_eq!(output.writes, SIZE as usize / CHUNK);
+        assert!(output.largest_write <= CHUNK);
+
+        assert!(transport
+            .stream_resource("file:///missing", &mut output)
+            .await
+            .is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_streamed_resource_without_endpoint() {
+        use base64::Engine;
+
+        let transport = HttpTransport::new(streamed_server(3 * CHUNK as u64 + 5));
+        let read = r#"{"jsonrpc":"2.0","id":1,"method":"resources/read","params":{"uri":"file:///big file.bin"}}"#;
+        let response = transport.handle(&HttpRequest::post(read));
+        let body: Value = serde_json::from_str(&response.body).unwrap();
+        let blob = body["result"]["contents"][0]["blob"].as_str().unwrap();
+        assert_eq!(crate::BASE64.decode(blob).unwrap().len(), 3 * CHUNK + 5);
+    }
+}
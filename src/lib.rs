@@ -1,231 +1,7110 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
+use std::io::{BufRead, Write};
+use std::ops::Deref;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "async")]
+mod async_session;
+mod builder;
+mod client;
+mod config;
+mod directory;
+mod error;
+mod http;
+mod macros;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod record;
+mod stdio;
+mod transport;
+#[cfg(feature = "watch")]
+mod watch;
+
+#[cfg(feature = "async")]
+pub use async_session::BatchOrdering;
+pub use builder::ServerBuilder;
+pub use client::Client;
+#[cfg(feature = "async")]
+pub use client::NotificationStream;
+pub use config::ServerConfig;
+pub use directory::DirectoryOptions;
+pub use error::{BuildError, Diagnostic, ErrorCode, Inconsistency, McpError};
+pub use http::{
+    AuthMiddleware, HttpHandler, HttpRequest, HttpResponse, HttpTransport, LoggingMiddleware,
+};
+#[doc(hidden)]
+pub use macros::support as __tool_support;
+pub use macros::ToolArgument;
+pub use record::{replay, Recorded, ReplayMismatch};
+pub use stdio::{FramedTransport, Framing, StdioTransport, DEFAULT_MAX_MESSAGE_SIZE};
+#[cfg(feature = "async")]
+pub use transport::{AsyncTransport, LinesTransport};
+pub use transport::{Direction, LoggingTransport, Transport};
 
 // Core protocol types
 const JSONRPC_VERSION: &str = "2.0";
 const PROTOCOL_VERSION: &str = "2024-11-05";
 
-#[derive(Debug, Serialize, Deserialize)]
+// Optional `Value` fields keep an explicit `null` as `Some(Value::Null)` so it
+// stays distinct from an absent field and round-trips unchanged
+fn deserialize_present<'de, D>(deserializer: D) -> Result<Option<Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Request {
     jsonrpc: String,
     id: RequestId,
     method: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
     params: Option<Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Response {
     jsonrpc: String,
     id: RequestId,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
     result: Option<Value>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     error: Option<ErrorResponse>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Notification {
     jsonrpc: String,
     method: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
     params: Option<Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl Request {
+    pub fn new(id: impl Into<RequestId>, method: &str, params: Option<Value>) -> Self {
+        Request {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id: id.into(),
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    pub fn id(&self) -> &RequestId {
+        &self.id
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn params(&self) -> Option<&Value> {
+        self.params.as_ref()
+    }
+}
+
+impl Response {
+    /// A response carrying either `result` or `error`, never both.
+    pub fn from_result(id: RequestId, result: Result<Value, ErrorResponse>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Response {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+
+    /// A successful response, for transports and adapters answering requests
+    /// themselves.
+    ///
+    /// ```
+    /// use mcp_rs::{RequestId, Response};
+    ///
+    /// let response = Response::success(RequestId::Number(1), serde_json::json!({}));
+    /// assert_eq!(
+    ///     serde_json::to_string(&response).unwrap(),
+    ///     r#"{"jsonrpc":"2.0","id":1,"result":{}}"#
+    /// );
+    /// ```
+    pub fn success(id: RequestId, result: Value) -> Self {
+        Response::from_result(id, Ok(result))
+    }
+
+    /// An error response; see `ErrorResponse::new`.
+    pub fn failure(id: RequestId, error: ErrorResponse) -> Self {
+        Response::from_result(id, Err(error))
+    }
+
+    pub fn id(&self) -> &RequestId {
+        &self.id
+    }
+
+    pub fn result(&self) -> Option<&Value> {
+        self.result.as_ref()
+    }
+
+    pub fn error(&self) -> Option<&ErrorResponse> {
+        self.error.as_ref()
+    }
+}
+
+impl Notification {
+    pub fn new(method: &str, params: Option<Value>) -> Self {
+        Notification {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    /// `notifications/progress` for the request that sent `token`, optionally
+    /// describing the current step in `message`.
+    pub fn progress(
+        token: ProgressToken,
+        progress: f64,
+        total: Option<f64>,
+        message: Option<&str>,
+    ) -> Self {
+        let mut params = serde_json::json!({
+            "progressToken": token,
+            "progress": progress,
+        });
+        if let Some(total) = total {
+            params["total"] = total.into();
+        }
+        if let Some(message) = message {
+            params["message"] = message.into();
+        }
+        Self::new("notifications/progress", Some(params))
+    }
+
+    /// `notifications/resources/updated` for the resource at `uri`.
+    pub fn resource_updated(uri: &str) -> Self {
+        Self::new(
+            "notifications/resources/updated",
+            Some(serde_json::json!({ "uri": uri })),
+        )
+    }
+
+    /// `notifications/resources/list_changed`, sent when resources are added
+    /// or removed.
+    pub fn resource_list_changed() -> Self {
+        Self::new("notifications/resources/list_changed", None)
+    }
+
+    /// `notifications/message` carrying a log record. `data` may be any JSON
+    /// value, a plain message or a structured record alike.
+    pub fn message(level: LoggingLevel, logger: Option<&str>, data: impl Into<Value>) -> Self {
+        let mut params = serde_json::json!({
+            "level": level,
+            "data": data.into(),
+        });
+        if let Some(logger) = logger {
+            params["logger"] = logger.into();
+        }
+        Self::new("notifications/message", Some(params))
+    }
+
+    /// `notifications/cancelled` for the request `id`.
+    pub fn cancelled(id: RequestId, reason: Option<&str>) -> Self {
+        let mut params = serde_json::json!({ "requestId": id });
+        if let Some(reason) = reason {
+            params["reason"] = reason.into();
+        }
+        Self::new("notifications/cancelled", Some(params))
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn params(&self) -> Option<&Value> {
+        self.params.as_ref()
+    }
+}
+
+// Syslog severities used by MCP logging, least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoggingLevel {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(untagged)]
 pub enum RequestId {
     String(String),
     Number(i64),
+    /// Only used in error responses to messages whose id couldn't be determined.
+    Null,
+}
+
+impl From<i64> for RequestId {
+    fn from(id: i64) -> Self {
+        RequestId::Number(id)
+    }
+}
+
+impl From<&str> for RequestId {
+    fn from(id: &str) -> Self {
+        RequestId::String(id.to_string())
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl From<String> for RequestId {
+    fn from(id: String) -> Self {
+        RequestId::String(id)
+    }
+}
+
+// Progress tokens share the string-or-integer shape of request ids
+pub type ProgressToken = RequestId;
+
+// Params of `notifications/cancelled`; every field is optional so partial
+// notifications are tolerated rather than rejected
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CancelledParams {
+    request_id: Option<RequestId>,
+}
+
+pub type HandlerResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ErrorResponse {
     code: i32,
     message: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
     data: Option<Value>,
 }
 
-// Server implementation
-pub struct Server {
-    capabilities: ServerCapabilities,
-    implementation: Implementation,
+impl ErrorResponse {
+    /// An error for a handler to return when it wants the client to get this
+    /// exact JSON-RPC error rather than a generic internal error.
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ErrorResponse {
+            code: code.as_i32(),
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Like `new`, with a code outside `ErrorCode`, such as an
+    /// application-defined one.
+    pub fn with_code(code: i32, message: impl Into<String>) -> Self {
+        ErrorResponse {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Attaches structured details, sent as the error's `data`.
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn data(&self) -> Option<&Value> {
+        self.data.as_ref()
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ServerCapabilities {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    logging: Option<Value>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    prompts: Option<PromptsCapability>,
+impl std::fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl Error for ErrorResponse {}
+
+// A handler's error as sent to the client: an `ErrorResponse` as is, anything
+// else as an internal error carrying its message
+fn handler_error(err: Box<dyn Error + Send + Sync>) -> ErrorResponse {
+    match err.downcast::<ErrorResponse>() {
+        Ok(error) => *error,
+        Err(err) => ErrorResponse::new(ErrorCode::InternalError, err.to_string()),
+    }
+}
+
+// Elicitation types
+
+/// The client's answer to an `elicitation/create` request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ElicitationResult {
+    /// The user submitted `content`, shaped by the requested schema.
+    Accept {
+        content: Value,
+    },
+    Decline,
+    Cancel,
+}
+
+// Sampling types
+
+/// The client's answer to a `sampling/createMessage` request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateMessageResult {
+    pub role: Role,
+    pub content: Content,
+    /// The model that generated the message.
+    pub model: String,
+    /// Why sampling stopped, e.g. `endTurn`, `maxTokens`, or `stopSequence`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+}
+
+// Invoked with the client's result or error once it answers a server-initiated request
+type ResponseCallback = Box<dyn FnOnce(Result<Value, ErrorResponse>) + Send>;
+
+type ReadyCallback = Box<dyn FnOnce() + Send>;
+
+// Resource types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    resources: Option<ResourcesCapability>,
+    pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    tools: Option<ToolsCapability>,
+    pub mime_type: Option<String>,
+    /// The size of the contents in bytes, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Implementation {
-    name: String,
-    version: String,
+/// Hints for clients about who an item is meant for and how much it matters.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Annotations {
+    /// Who the item is intended for: the user, the model, or both.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<Vec<Role>>,
+    /// Importance from 0 (optional) to 1 (effectively required).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<f64>,
+    /// When the item was last modified, as an RFC 3339 timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct PromptsCapability {
-    list_changed: bool,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResourceContents {
+    #[serde(rename_all = "camelCase")]
+    Text {
+        uri: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+        text: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Blob {
+        uri: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+        blob: String,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ResourcesCapability {
-    subscribe: bool,
-    list_changed: bool,
+type ResourceReader = Arc<dyn Fn(&RequestContext) -> HandlerResult<ResourceContents> + Send + Sync>;
+
+/// One slice of a chunked resource, starting at the offset the reader was given.
+#[derive(Debug, Clone)]
+pub struct ResourceChunk {
+    pub data: Vec<u8>,
+    /// Size of the whole resource in bytes.
+    pub total_size: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ToolsCapability {
-    list_changed: bool,
+type ChunkReader =
+    Arc<dyn Fn(u64, usize, &RequestContext) -> HandlerResult<ResourceChunk> + Send + Sync>;
+
+/// The body of a streamed resource, produced a chunk at a time as it is read.
+#[cfg(feature = "async")]
+pub type ByteStream = std::pin::Pin<
+    Box<dyn futures_core::Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send>,
+>;
+
+#[cfg(feature = "async")]
+type StreamOpener = Arc<dyn Fn(&RequestContext) -> HandlerResult<ByteStream> + Send + Sync>;
+
+#[derive(Clone)]
+enum ResourceSource {
+    Contents(ResourceReader),
+    Chunked {
+        chunk_size: usize,
+        reader: ChunkReader,
+    },
+    #[cfg(feature = "async")]
+    Streamed(StreamOpener),
 }
 
-impl Server {
-    pub fn new(name: &str, version: &str) -> Self {
-        Server {
-            capabilities: ServerCapabilities {
-                logging: Some(Value::Object(serde_json::Map::new())),
-                prompts: Some(PromptsCapability {
-                    list_changed: false,
-                }),
-                resources: Some(ResourcesCapability {
-                    subscribe: false,
-                    list_changed: false,
-                }),
-                tools: Some(ToolsCapability {
-                    list_changed: false,
-                }),
-            },
-            implementation: Implementation {
-                name: name.to_string(),
-                version: version.to_string(),
-            },
+#[derive(Clone)]
+struct RegisteredResource {
+    resource: Resource,
+    reader: ResourceSource,
+}
+
+/// A family of resources whose URIs follow `uri_template`, e.g.
+/// `file:///logs/{date}`. Each `{variable}` matches one non-empty path
+/// segment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceTemplate {
+    pub uri_template: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+impl ResourceTemplate {
+    /// The names of the template's `{variables}`, in the order they appear.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables = Vec::new();
+        let mut template = self.uri_template.as_str();
+        while let Some(open) = template.find('{') {
+            let Some(close) = template[open..].find('}') else {
+                break;
+            };
+            variables.push(&template[open + 1..open + close]);
+            template = &template[open + close + 1..];
         }
+        variables
     }
 
-    pub fn handle_message(&self, message: &str) -> Result<Option<String>, Box<dyn Error>> {
-        let parsed: Value = serde_json::from_str(message)?;
+    /// The variables bound by `uri`, or `None` if it doesn't match the template.
+    /// Values are percent-decoded; each covers part of a single path segment.
+    pub fn match_uri(&self, uri: &str) -> Option<HashMap<String, String>> {
+        let mut variables = HashMap::new();
+        let mut rest = uri;
+        let mut template = self.uri_template.as_str();
+        while !template.is_empty() {
+            let Some(open) = template.find('{') else {
+                rest = rest.strip_prefix(template)?;
+                break;
+            };
+            rest = rest.strip_prefix(&template[..open])?;
+            let close = open + template[open..].find('}')?;
+            let name = &template[open + 1..close];
+            template = &template[close + 1..];
 
-        // Handle request vs notification
-        if parsed.get("id").is_some() {
-            self.handle_request(message)
-        } else {
-            self.handle_notification(message)?;
-            Ok(None)
+            // A variable runs up to the next literal part of the template
+            let literal = &template[..template.find('{').unwrap_or(template.len())];
+            let end = if literal.is_empty() {
+                rest.len()
+            } else {
+                rest.find(literal)?
+            };
+            let value = &rest[..end];
+            if value.is_empty() {
+                return None;
+            }
+            // Checked after decoding, so an escaped `%2F` can't smuggle a
+            // separator into what must stay a single segment
+            let value = percent_decode(value)?;
+            if value.contains(['/', '\\', '\0']) {
+                return None;
+            }
+            variables.insert(name.to_string(), value);
+            rest = &rest[end..];
         }
+        rest.is_empty().then_some(variables)
     }
+}
 
-    fn handle_request(&self, message: &str) -> Result<Option<String>, Box<dyn Error>> {
-        let request: Request = serde_json::from_str(message)?;
+const TRUNCATION_MARKER: &str = "\n[truncated]";
 
-        match request.method.as_str() {
-            "initialize" => {
-                let response = Response {
-                    jsonrpc: JSONRPC_VERSION.to_string(),
-                    id: request.id,
-                    result: Some(serde_json::json!({
-                        "protocolVersion": PROTOCOL_VERSION,
-                        "capabilities": self.capabilities,
-                        "serverInfo": self.implementation,
-                    })),
-                    error: None,
-                };
-                Ok(Some(serde_json::to_string(&response)?))
-            }
-            "ping" => {
-                let response = Response {
-                    jsonrpc: JSONRPC_VERSION.to_string(),
-                    id: request.id,
-                    result: Some(Value::Object(serde_json::Map::new())),
-                    error: None,
-                };
-                Ok(Some(serde_json::to_string(&response)?))
+// Cuts the text blocks of a tool result down to `max` bytes in total, marking
+// the cut and dropping text past it. Reports whether anything was removed.
+fn truncate_text(content: &mut Vec<Content>, max: usize) -> bool {
+    let mut remaining = max;
+    let mut truncated = false;
+    content.retain_mut(|item| {
+        let Content::Text { text } = item else {
+            return true;
+        };
+        if truncated {
+            return false;
+        }
+        if text.len() <= remaining {
+            remaining -= text.len();
+            return true;
+        }
+        let mut end = remaining;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str(TRUNCATION_MARKER);
+        truncated = true;
+        true
+    });
+    truncated
+}
+
+fn timeout_response(id: RequestId) -> Response {
+    Response::from_result(
+        id,
+        Err(ErrorResponse {
+            code: ErrorCode::RequestTimeout.as_i32(),
+            message: "Request timed out".to_string(),
+            data: None,
+        }),
+    )
+}
+
+// The absolute `_meta.deadline` of a request, ignored if it isn't RFC 3339
+fn request_deadline(params: Option<&Value>) -> Option<SystemTime> {
+    parse_rfc3339(params?.get("_meta")?.get("deadline")?.as_str()?)
+}
+
+// The W3C `_meta.traceparent` of a request, such as
+// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`, ignored if malformed
+fn request_traceparent(params: Option<&Value>) -> Option<String> {
+    let traceparent = params?.get("_meta")?.get("traceparent")?.as_str()?;
+    let fields: Vec<&str> = traceparent.split('-').collect();
+    let well_formed = fields.len() == 4
+        && fields
+            .iter()
+            .zip([2, 32, 16, 2])
+            .all(|(field, len)| field.len() == len && field.bytes().all(|b| b.is_ascii_hexdigit()));
+    well_formed.then(|| traceparent.to_string())
+}
+
+// Parses timestamps such as `2025-01-31T12:00:00.5Z` or `2025-01-31T13:00:00+01:00`
+fn parse_rfc3339(timestamp: &str) -> Option<SystemTime> {
+    let bytes = timestamp.as_bytes();
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if bytes.len() < 20
+        || separators.iter().any(|&(at, sep)| bytes[at] != sep)
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+    {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = &bytes[range];
+        digits.iter().try_fold(0, |n, &b| {
+            b.is_ascii_digit().then(|| n * 10 + i64::from(b - b'0'))
+        })
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    // A leap second is folded into the following one
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &bytes[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix(b".") {
+        let digits = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        for (i, digit) in fraction[..digits.min(9)].iter().enumerate() {
+            nanos += u32::from(digit - b'0') * 10u32.pow(8 - i as u32);
+        }
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), ..] if rest.len() == 6 && rest[3] == b':' => {
+            let digits = [rest[1], rest[2], rest[4], rest[5]];
+            if !digits.iter().all(u8::is_ascii_digit) {
+                return None;
             }
-            _ => {
-                let error = ErrorResponse {
-                    code: -32601, // Method not found
-                    message: "Method not found".to_string(),
-                    data: None,
-                };
-                let response = Response {
-                    jsonrpc: JSONRPC_VERSION.to_string(),
-                    id: request.id,
-                    result: None,
-                    error: Some(error),
-                };
-                Ok(Some(serde_json::to_string(&response)?))
+            let [h1, h2, m1, m2] = digits.map(|b| i64::from(b - b'0'));
+            let seconds = ((h1 * 10 + h2) * 60 + m1 * 10 + m2) * 60;
+            if *sign == b'+' {
+                seconds
+            } else {
+                -seconds
             }
         }
-    }
+        _ => return None,
+    };
 
-    fn handle_notification(&self, message: &str) -> Result<(), Box<dyn Error>> {
-        let notification: Notification = serde_json::from_str(message)?;
+    // Days since the epoch in the proleptic Gregorian calendar
+    let shifted_year = if month <= 2 { year - 1 } else { year };
+    let era = shifted_year.div_euclid(400);
+    let year_of_era = shifted_year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
 
-        match notification.method.as_str() {
-            "notifications/initialized" => Ok(()),
-            _ => Ok(()),
+    let seconds = days * 86_400 + hour * 3_600 + minute * 60 + second - offset;
+    let since_epoch = Duration::new(seconds.unsigned_abs(), nanos);
+    if seconds >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(since_epoch)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(since_epoch)
+    }
+}
+
+// Decodes `%XX` escapes, failing on malformed ones or if the result isn't UTF-8
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
         }
     }
+    String::from_utf8(bytes).ok()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// Escapes everything but unreserved characters, for use in a query string
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
 
-    #[test]
-    fn test_initialization() {
-        let server = Server::new("test-server", "1.0.0");
+// Puts a URI in canonical form, with its scheme lowercased and the `.` and `..`
+// segments of a hierarchical path resolved, or `None` if it has no scheme
+fn normalize_uri(uri: &str) -> Option<String> {
+    let (scheme, rest) = uri.split_once(':')?;
+    let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    if !valid_scheme {
+        return None;
+    }
+    let scheme = scheme.to_ascii_lowercase();
+    let Some(hierarchical) = rest.strip_prefix("//") else {
+        return Some(format!("{scheme}:{rest}"));
+    };
+    // The query and fragment are kept as they are
+    let (hierarchical, suffix) =
+        hierarchical.split_at(hierarchical.find(['?', '#']).unwrap_or(hierarchical.len()));
+    let (authority, path) =
+        hierarchical.split_at(hierarchical.find('/').unwrap_or(hierarchical.len()));
+    Some(format!(
+        "{scheme}://{authority}{}{suffix}",
+        remove_dot_segments(path)
+    ))
+}
 
-        // Test initialize request
-        let init_request = serde_json::json!({
-            "jsonrpc": "2.0",
-            "id": 1,
-            "method": "initialize",
-            "params": {
-                "protocolVersion": "2024-11-05",
-                "capabilities": {},
-                "clientInfo": {
-                    "name": "test-client",
-                    "version": "1.0.0"
+// Resolves `.` and `..` in an absolute path, as in RFC 3986
+fn remove_dot_segments(path: &str) -> String {
+    if path.is_empty() {
+        return String::new();
+    }
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    let mut output = Vec::with_capacity(segments.len());
+    for (i, &segment) in segments.iter().enumerate() {
+        match segment {
+            "." | ".." => {
+                if segment == ".." {
+                    output.pop();
+                }
+                // A trailing dot segment still names a directory
+                if i + 1 == segments.len() {
+                    output.push("");
                 }
             }
-        });
+            _ => output.push(segment),
+        }
+    }
+    format!("/{}", output.join("/"))
+}
 
-        let response = server
-            .handle_message(&init_request.to_string())
-            .unwrap()
-            .unwrap();
-        let response_value: Value = serde_json::from_str(&response).unwrap();
+// Validates and normalizes the URI a resource is registered under
+fn normalize_resource(mut resource: Resource) -> Result<Resource, McpError> {
+    resource.uri = normalize_uri(&resource.uri).ok_or_else(|| {
+        McpError::Config(format!("resource URI `{}` has no scheme", resource.uri))
+    })?;
+    Ok(resource)
+}
 
-        assert_eq!(response_value["result"]["protocolVersion"], "2024-11-05");
-        assert!(response_value["result"]["capabilities"].is_object());
-    }
+// Validates and normalizes a template the same way, so that it matches the
+// normalized URIs of reads
+fn normalize_template(mut template: ResourceTemplate) -> Result<ResourceTemplate, McpError> {
+    template.uri_template = normalize_uri(&template.uri_template).ok_or_else(|| {
+        McpError::Config(format!(
+            "resource template `{}` has no scheme",
+            template.uri_template
+        ))
+    })?;
+    Ok(template)
+}
 
-    #[test]
-    fn test_ping() {
-        let server = Server::new("test-server", "1.0.0");
+type TemplateReader = Arc<
+    dyn Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<Vec<ResourceContents>>
+        + Send
+        + Sync,
+>;
 
-        let ping_request = serde_json::json!({
-            "jsonrpc": "2.0",
-            "id": 1,
-            "method": "ping"
-        });
+#[derive(Clone)]
+struct RegisteredTemplate {
+    template: ResourceTemplate,
+    reader: TemplateReader,
+}
 
-        let response = server
-            .handle_message(&ping_request.to_string())
-            .unwrap()
-            .unwrap();
-        let response_value: Value = serde_json::from_str(&response).unwrap();
+// Content types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Content {
+    Text {
+        text: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Image {
+        data: String,
+        mime_type: String,
+    },
+    Resource {
+        resource: ResourceContents,
+    },
+    /// A reference to a resource the client can read separately, for results too
+    /// large to embed.
+    #[serde(rename = "resource_link", rename_all = "camelCase")]
+    ResourceLink {
+        uri: String,
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+    },
+}
 
-        assert!(response_value["result"].is_object());
-        assert!(response_value["error"].is_null());
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+// Prompt types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prompt {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arguments: Vec<PromptArgument>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptArgument {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptMessage {
+    pub role: Role,
+    pub content: Content,
+}
+
+/// The result of `prompts/get`. Render closures may return one, or just the
+/// messages, in which case the prompt's own description is used.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub messages: Vec<PromptMessage>,
+}
+
+impl From<Vec<PromptMessage>> for PromptResult {
+    fn from(messages: Vec<PromptMessage>) -> Self {
+        PromptResult {
+            description: None,
+            messages,
+        }
+    }
+}
+
+type PromptRenderer = Arc<
+    dyn Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<PromptResult> + Send + Sync,
+>;
+
+// Erases the result type a render closure was written with
+fn prompt_renderer<F, R>(render: F) -> PromptRenderer
+where
+    F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<R> + Send + Sync + 'static,
+    R: Into<PromptResult>,
+{
+    Arc::new(move |arguments, ctx| render(arguments, ctx).map(Into::into))
+}
+
+#[derive(Clone)]
+struct RegisteredPrompt {
+    prompt: Prompt,
+    render: PromptRenderer,
+    completions: HashMap<String, Arc<dyn CompletionProvider>>,
+}
+
+// Tool types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub input_schema: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<ToolAnnotations>,
+    /// Extra metadata sent as the tool's `_meta`, such as the `usage` hint.
+    /// Keys this crate doesn't know are kept as they are.
+    #[serde(default, rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Map<String, Value>>,
+}
+
+impl Tool {
+    /// Guidance on using the tool well, for clients to display or pass on to
+    /// the model, sent as `_meta.usage` in `tools/list`.
+    pub fn usage(&self) -> Option<&str> {
+        self.meta.as_ref()?.get("usage")?.as_str()
+    }
+
+    /// Sets the `usage` hint, keeping any other `_meta` fields.
+    pub fn with_usage(mut self, usage: impl Into<String>) -> Self {
+        self.meta
+            .get_or_insert_with(serde_json::Map::new)
+            .insert("usage".to_string(), Value::String(usage.into()));
+        self
+    }
+}
+
+/// Hints about a tool's behavior that clients may use, for instance to decide
+/// whether a call can be approved automatically. They are not guarantees.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    /// The tool doesn't modify its environment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only_hint: Option<bool>,
+    /// The tool may perform destructive updates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destructive_hint: Option<bool>,
+    /// Repeating a call with the same arguments has no additional effect.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotent_hint: Option<bool>,
+    /// The tool interacts with entities outside the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_world_hint: Option<bool>,
+}
+
+type ToolHandler = Arc<dyn Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync>;
+
+type StructuredHandler = Arc<dyn Fn(Value, &RequestContext) -> HandlerResult<Value> + Send + Sync>;
+
+/// The kind of `tools/call` result a client would rather receive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContentPreference {
+    #[default]
+    Text,
+    /// A JSON value in `structuredContent`.
+    Structured,
+}
+
+// An explicit `_meta.preferredContent` hint wins over the client's declared
+// `capabilities.content.structured`
+fn content_preference(params: Option<&Value>, capabilities: Option<Value>) -> ContentPreference {
+    let hint = params
+        .and_then(|params| params.get("_meta"))
+        .and_then(|meta| meta.get("preferredContent"))
+        .and_then(Value::as_str);
+    match hint {
+        Some("structured") => ContentPreference::Structured,
+        Some("text") => ContentPreference::Text,
+        _ => {
+            let structured = capabilities
+                .as_ref()
+                .and_then(|capabilities| capabilities.get("content"))
+                .and_then(|content| content.get("structured"))
+                .is_some_and(|structured| structured != &Value::Bool(false));
+            if structured {
+                ContentPreference::Structured
+            } else {
+                ContentPreference::Text
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RegisteredTool {
+    tool: Tool,
+    handler: ToolHandler,
+    // Used instead of `handler` for clients preferring structured content
+    structured: Option<StructuredHandler>,
+    // Overrides the session's request timeout for this tool in the async path
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    timeout: Option<Duration>,
+}
+
+type MethodHandler =
+    Arc<dyn Fn(Option<Value>, &RequestContext) -> HandlerResult<Value> + Send + Sync>;
+
+type NotificationHandler = Arc<dyn Fn(Option<Value>) -> Result<(), McpError> + Send + Sync>;
+
+type ParamDecorator = Arc<dyn Fn(&str, &mut Value) + Send + Sync>;
+
+// Completion types
+
+// The spec caps a single completion response at 100 values
+const MAX_COMPLETION_VALUES: usize = 100;
+
+/// Suggests values for a prompt argument given what the user has typed so far.
+pub trait CompletionProvider: Send + Sync {
+    fn complete(&self, value: &str) -> Vec<String>;
+
+    /// Like `complete`, also given the arguments the user has already resolved
+    /// (`params.context.arguments`), keyed by name. Ignores them by default.
+    fn complete_in_context(
+        &self,
+        value: &str,
+        _arguments: &HashMap<String, String>,
+    ) -> Vec<String> {
+        self.complete(value)
+    }
+
+    /// The values actually sent to the client. Defaults to the plain values
+    /// from `complete_in_context`; override it to attach display labels.
+    fn complete_labeled(
+        &self,
+        value: &str,
+        arguments: &HashMap<String, String>,
+    ) -> Vec<CompletionValue> {
+        self.complete_in_context(value, arguments)
+            .into_iter()
+            .map(CompletionValue::Plain)
+            .collect()
+    }
+}
+
+/// One entry of `completion.values`: a bare string, or a value with a
+/// friendlier label for the client to display.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CompletionValue {
+    Plain(String),
+    Labeled { value: String, label: String },
+}
+
+impl From<String> for CompletionValue {
+    fn from(value: String) -> Self {
+        CompletionValue::Plain(value)
+    }
+}
+
+impl From<&str> for CompletionValue {
+    fn from(value: &str) -> Self {
+        CompletionValue::Plain(value.to_string())
+    }
+}
+
+impl CompletionValue {
+    pub fn labeled(value: &str, label: &str) -> Self {
+        CompletionValue::Labeled {
+            value: value.to_string(),
+            label: label.to_string(),
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            CompletionValue::Plain(value) | CompletionValue::Labeled { value, .. } => value,
+        }
+    }
+}
+
+impl<F> CompletionProvider for F
+where
+    F: Fn(&str) -> Vec<String> + Send + Sync,
+{
+    fn complete(&self, value: &str) -> Vec<String> {
+        self(value)
+    }
+}
+
+/// Adapts a closure that also takes the already-resolved arguments into a
+/// `CompletionProvider`.
+pub struct ContextualCompletion<F>(pub F);
+
+impl<F> CompletionProvider for ContextualCompletion<F>
+where
+    F: Fn(&str, &HashMap<String, String>) -> Vec<String> + Send + Sync,
+{
+    fn complete(&self, value: &str) -> Vec<String> {
+        (self.0)(value, &HashMap::new())
+    }
+
+    fn complete_in_context(&self, value: &str, arguments: &HashMap<String, String>) -> Vec<String> {
+        (self.0)(value, arguments)
+    }
+}
+
+/// Adapts a closure returning labeled values into a `CompletionProvider`.
+pub struct LabeledCompletion<F>(pub F);
+
+impl<F> CompletionProvider for LabeledCompletion<F>
+where
+    F: Fn(&str) -> Vec<CompletionValue> + Send + Sync,
+{
+    fn complete(&self, value: &str) -> Vec<String> {
+        (self.0)(value)
+            .into_iter()
+            .map(|value| value.value().to_string())
+            .collect()
+    }
+
+    fn complete_labeled(
+        &self,
+        value: &str,
+        _arguments: &HashMap<String, String>,
+    ) -> Vec<CompletionValue> {
+        (self.0)(value)
+    }
+}
+
+// Per-request information handed to handlers
+pub struct RequestContext {
+    id: RequestId,
+    progress_token: Option<ProgressToken>,
+    deadline: Option<SystemTime>,
+    traceparent: Option<String>,
+    cancellation: Arc<Cancellation>,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl RequestContext {
+    pub fn id(&self) -> &RequestId {
+        &self.id
+    }
+
+    /// Sends a request such as `sampling/createMessage` to the client; see
+    /// `Session::send_request`. The request carries this one's `traceparent`,
+    /// unless `params` sets its own.
+    pub fn send_request<F>(&self, method: &str, mut params: Option<Value>, on_response: F)
+    where
+        F: FnOnce(Result<Value, ErrorResponse>) + Send + 'static,
+    {
+        if let Some(traceparent) = &self.traceparent {
+            let params = params.get_or_insert_with(|| serde_json::json!({}));
+            if let Some(params) = params.as_object_mut() {
+                let meta = params
+                    .entry("_meta")
+                    .or_insert_with(|| serde_json::json!({}));
+                if let Some(meta) = meta.as_object_mut() {
+                    meta.entry("traceparent")
+                        .or_insert_with(|| Value::from(traceparent.as_str()));
+                }
+            }
+        }
+        self.state
+            .lock()
+            .unwrap()
+            .send_request(method, params, Box::new(on_response));
+    }
+
+    /// The W3C trace context sent as `_meta.traceparent`, if it was well formed,
+    /// for attaching to tracing spans.
+    pub fn traceparent(&self) -> Option<&str> {
+        self.traceparent.as_deref()
+    }
+
+    /// The `_meta.progressToken` sent with the request, if any.
+    pub fn progress_token(&self) -> Option<&ProgressToken> {
+        self.progress_token.as_ref()
+    }
+
+    /// The capabilities the client declared in `initialize`, if it has sent one.
+    pub fn client_capabilities(&self) -> Option<Value> {
+        self.state.lock().unwrap().client_capabilities()
+    }
+
+    /// Whether the client accepts image content. Clients are assumed to unless
+    /// they declare a `content` capability without an `image` entry.
+    pub fn client_supports_images(&self) -> bool {
+        self.client_capabilities()
+            .and_then(|capabilities| capabilities.get("content").cloned())
+            .is_none_or(|content| content.get("image").is_some())
+    }
+
+    /// The `_meta.deadline` sent with the request, if any. In the async path
+    /// the request times out once it passes.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.deadline
+    }
+
+    /// Whether the client has sent `notifications/cancelled` for this request.
+    /// Long-running handlers can poll this to stop early.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Queues a `notifications/message` if `level` meets the server's log level.
+    pub fn log(&self, level: LoggingLevel, data: impl Into<Value>) {
+        self.state.lock().unwrap().log(level, None, data.into());
+    }
+
+    /// Returns a reporter for this request, or `None` if the client did not ask for progress.
+    pub fn progress(&self) -> Option<ProgressReporter> {
+        self.progress_token.clone().map(|token| ProgressReporter {
+            token,
+            state: Arc::clone(&self.state),
+        })
+    }
+}
+
+pub struct ProgressReporter {
+    token: ProgressToken,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl ProgressReporter {
+    /// Queues a `notifications/progress` for the request this reporter belongs
+    /// to. `message` is a human-readable description of the current step.
+    pub fn report(&self, progress: f64, total: Option<f64>, message: Option<&str>) {
+        self.state.lock().unwrap().enqueue(Notification::progress(
+            self.token.clone(),
+            progress,
+            total,
+            message,
+        ));
+    }
+}
+
+// Some clients prefix messages with a UTF-8 byte order mark, which isn't valid
+// JSON. Only a single leading one is removed.
+fn strip_bom(message: &str) -> &str {
+    message.strip_prefix('\u{FEFF}').unwrap_or(message)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Request,
+    Notification,
+    Response,
+    Batch,
+}
+
+/// What `Server::inspect` found out about a message. Batches have neither a
+/// method nor an id; responses have no method.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageInfo {
+    pub kind: MessageKind,
+    pub method: Option<String>,
+    pub id: Option<RequestId>,
+}
+
+// What a message is answered with: a single response, or an array of them
+// for a batch
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Reply {
+    Single(Response),
+    Batch(Vec<Response>),
+}
+
+// The id of a batch entry that is a request, as opposed to a notification or
+// a response to the server
+fn batch_request_id(message: &Value) -> Option<RequestId> {
+    message.get("method")?;
+    serde_json::from_value(message.get("id")?.clone()).ok()
+}
+
+fn duplicate_batch_ids(batch: &[Value]) -> HashSet<RequestId> {
+    let mut seen = HashSet::new();
+    batch
+        .iter()
+        .filter_map(batch_request_id)
+        .filter(|id| !seen.insert(id.clone()))
+        .collect()
+}
+
+// What is wrong with `response` as the answer to a request with `request_id`,
+// if anything. A request whose id isn't valid can only be answered with null.
+fn response_violation(response: &Response, request_id: &Value) -> Option<String> {
+    if response.jsonrpc != JSONRPC_VERSION {
+        return Some(format!("jsonrpc is {:?}, not \"2.0\"", response.jsonrpc));
+    }
+    if response.result.is_some() == response.error.is_some() {
+        return Some("exactly one of result and error must be present".to_string());
+    }
+    let expected = serde_json::from_value(request_id.clone()).unwrap_or(RequestId::Null);
+    if response.id != expected {
+        return Some(format!(
+            "id {:?} doesn't match the request's {:?}",
+            response.id, expected
+        ));
+    }
+    None
+}
+
+const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+
+// The answer to a message whose handling failed other than on I/O, so that it
+// doesn't end a serve loop
+fn unhandled_message(message: &str) -> Response {
+    let id = serde_json::from_str::<Value>(strip_bom(message))
+        .ok()
+        .and_then(|message| serde_json::from_value(message["id"].clone()).ok())
+        .unwrap_or(RequestId::Null);
+    invalid_request(id, "Invalid Request")
+}
+
+fn invalid_request(id: RequestId, message: &str) -> Response {
+    Response::from_result(
+        id,
+        Err(ErrorResponse {
+            code: ErrorCode::InvalidRequest.as_i32(),
+            message: message.to_string(),
+            data: None,
+        }),
+    )
+}
+
+// Server implementation
+
+/// What to do when a session receives `initialize` a second time, e.g. from a
+/// reconnecting client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReinitPolicy {
+    /// Reject it as an invalid request.
+    #[default]
+    Error,
+    /// Answer it again if its params are identical to the first; reject it otherwise.
+    Idempotent,
+    /// Start over as if the session were new; see `Session::reset`.
+    AllowReset,
+}
+
+/// Configuration and handler registries that stay the same for every
+/// connection. Share one core between connections with `Arc` and give each
+/// connection its own `Session` via `new_session`.
+#[derive(Clone)]
+pub struct ServerCore {
+    capabilities: ServerCapabilities,
+    implementation: Implementation,
+    resources: Vec<RegisteredResource>,
+    templates: Vec<RegisteredTemplate>,
+    prompts: Vec<RegisteredPrompt>,
+    tools: Vec<RegisteredTool>,
+    methods: HashMap<String, MethodHandler>,
+    notification_handlers: HashMap<String, NotificationHandler>,
+    instructions: Option<String>,
+    param_decorator: Option<ParamDecorator>,
+    recommended_log_level: Option<LoggingLevel>,
+    page_size: Option<usize>,
+    reinit_policy: ReinitPolicy,
+    ping_details: bool,
+    max_content_bytes: Option<usize>,
+    denied_methods: HashSet<String>,
+    // When set, only these methods are dispatched
+    allowed_methods: Option<HashSet<String>>,
+    request_ids: IdGenerator,
+}
+
+/// Hands out the ids of server-initiated requests. Every session of a core,
+/// and every clone of it, draws from the same counter, so ids are unique
+/// across sessions and a response can never be correlated with another
+/// session's request, e.g. when a transport multiplexes them.
+#[derive(Debug, Clone)]
+struct IdGenerator(Arc<AtomicI64>);
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        IdGenerator(Arc::new(AtomicI64::new(1)))
+    }
+}
+
+impl IdGenerator {
+    fn next(&self) -> RequestId {
+        RequestId::Number(self.0.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Set when the client cancels a running request. The handler sees it through
+/// `RequestContext::is_cancelled`, and the async path is woken to stop waiting.
+#[derive(Debug, Default)]
+struct Cancellation {
+    cancelled: AtomicBool,
+    #[cfg(feature = "async")]
+    abort: tokio::sync::Notify,
+}
+
+impl Cancellation {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        #[cfg(feature = "async")]
+        self.abort.notify_one();
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Per-connection state (lifecycle, subscriptions, cancellation, queued
+/// notifications) on top of a shared `ServerCore`. Derefs to the core for
+/// read-only access to its registries.
+pub struct Session {
+    core: Arc<ServerCore>,
+    state: Arc<Mutex<ServerState>>,
+    #[cfg(feature = "watch")]
+    watchers: Mutex<Vec<watch::ResourceWatcher>>,
+}
+
+/// A server for a single connection: a `ServerCore` paired with one `Session`.
+/// Derefs to the session for message handling.
+pub struct Server {
+    session: Session,
+}
+
+// Mutable state shared with background tasks such as resource watchers
+struct ServerState {
+    subscriptions: HashSet<String>,
+    // Resources the client may have missed updates to, announced on the next
+    // subscribe; kept across `reset` so a reconnecting client gets them
+    dirty_resources: HashSet<String>,
+    max_subscriptions: Option<usize>,
+    max_batch_size: usize,
+    notifications: VecDeque<Notification>,
+    notification_capacity: Option<usize>,
+    dropped_notifications: u64,
+    log_level: LoggingLevel,
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    request_timeout: Option<Duration>,
+    resource_cache_ttl: Option<Duration>,
+    resource_cache: HashMap<String, (Instant, ResourceContents)>,
+    dedup_window: Option<Duration>,
+    // Responses to recent requests, replayed if the same id is delivered again
+    recent_responses: HashMap<RequestId, (Instant, Response)>,
+    // Shared with the core this session belongs to
+    request_ids: IdGenerator,
+    pending_requests: HashMap<RequestId, ResponseCallback>,
+    // Server-initiated requests waiting to be written by the serve loop
+    outgoing: VecDeque<Request>,
+    // Wakes the async serve loop when something is queued for the client
+    #[cfg(feature = "async")]
+    outbox_ready: Arc<tokio::sync::Notify>,
+    // Bounds the requests running at once in the async path
+    #[cfg(feature = "async")]
+    concurrency_limit: Option<Arc<tokio::sync::Semaphore>>,
+    #[cfg(feature = "async")]
+    batch_ordering: BatchOrdering,
+    // Requests currently running, so a cancellation can reach them; each is
+    // removed once it finishes
+    in_flight: HashMap<RequestId, Arc<Cancellation>>,
+    verbose_errors: bool,
+    pretty_output: bool,
+    stats: Stats,
+    initialized: bool,
+    log_level_advertised: bool,
+    // Params of the `initialize` this session answered, compared on a repeat
+    init_params: Option<Value>,
+    // Run once both `initialize` and `notifications/initialized` were handled
+    on_ready: Option<ReadyCallback>,
+    // Checks each response against JSON-RPC before it is sent
+    validate_outgoing: bool,
+    // Where a transport serves streamed resource bodies, if it does
+    #[cfg(feature = "async")]
+    stream_endpoint: Option<String>,
+    // Receives every handled message and response once recording is enabled
+    recording: Option<std::fs::File>,
+    exiting: bool,
+}
+
+// Request counters reported by the `x-mcp/stats` method
+struct Stats {
+    started: Instant,
+    total_requests: u64,
+    errors: u64,
+    per_method: HashMap<String, u64>,
+    #[cfg(feature = "metrics")]
+    errors_by_code: HashMap<i32, u64>,
+    #[cfg(feature = "metrics")]
+    latency: metrics::Histogram,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Stats {
+            started: Instant::now(),
+            total_requests: 0,
+            errors: 0,
+            per_method: HashMap::new(),
+            #[cfg(feature = "metrics")]
+            errors_by_code: HashMap::new(),
+            #[cfg(feature = "metrics")]
+            latency: metrics::Histogram::default(),
+        }
+    }
+
+    // The error code and latency are only kept for `metrics_prometheus`
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn record(&mut self, method: &str, error_code: Option<i32>, elapsed: Duration) {
+        self.total_requests += 1;
+        if let Some(code) = error_code {
+            self.errors += 1;
+            #[cfg(feature = "metrics")]
+            {
+                *self.errors_by_code.entry(code).or_default() += 1;
+            }
+        }
+        *self.per_method.entry(method.to_string()).or_default() += 1;
+        #[cfg(feature = "metrics")]
+        self.latency.observe(elapsed);
+    }
+
+    fn to_value(&self) -> Value {
+        serde_json::json!({
+            "totalRequests": self.total_requests,
+            "errorCount": self.errors,
+            "methods": self.per_method,
+            "uptimeSeconds": self.started.elapsed().as_secs_f64(),
+        })
+    }
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        ServerState {
+            subscriptions: HashSet::new(),
+            dirty_resources: HashSet::new(),
+            max_subscriptions: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            notifications: VecDeque::new(),
+            notification_capacity: None,
+            dropped_notifications: 0,
+            log_level: LoggingLevel::Info,
+            request_timeout: None,
+            resource_cache_ttl: None,
+            resource_cache: HashMap::new(),
+            dedup_window: None,
+            recent_responses: HashMap::new(),
+            request_ids: IdGenerator::default(),
+            pending_requests: HashMap::new(),
+            outgoing: VecDeque::new(),
+            #[cfg(feature = "async")]
+            outbox_ready: Arc::new(tokio::sync::Notify::new()),
+            #[cfg(feature = "async")]
+            concurrency_limit: None,
+            #[cfg(feature = "async")]
+            batch_ordering: BatchOrdering::default(),
+            in_flight: HashMap::new(),
+            verbose_errors: false,
+            pretty_output: false,
+            stats: Stats::new(),
+            initialized: false,
+            log_level_advertised: false,
+            init_params: None,
+            on_ready: None,
+            validate_outgoing: false,
+            #[cfg(feature = "async")]
+            stream_endpoint: None,
+            recording: None,
+            exiting: false,
+        }
+    }
+}
+
+impl ServerState {
+    fn client_capabilities(&self) -> Option<Value> {
+        self.init_params.as_ref()?.get("capabilities").cloned()
+    }
+
+    // Registers `id` as running, or joins the registration made for it before
+    // it was handed to another task
+    fn begin_request(&mut self, id: &RequestId) -> Arc<Cancellation> {
+        Arc::clone(self.in_flight.entry(id.clone()).or_default())
+    }
+
+    // Unregisters `id`, unless it has since been taken by a newer request
+    fn finish_request(&mut self, id: &RequestId, cancellation: &Arc<Cancellation>) {
+        if self
+            .in_flight
+            .get(id)
+            .is_some_and(|current| Arc::ptr_eq(current, cancellation))
+        {
+            self.in_flight.remove(id);
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.initialized && self.init_params.is_some()
+    }
+
+    // The ready callback, if it is due; it must be called without the lock held
+    fn take_ready_callback(&mut self) -> Option<ReadyCallback> {
+        if self.is_ready() {
+            self.on_ready.take()
+        } else {
+            None
+        }
+    }
+
+    fn reset(&mut self) {
+        self.subscriptions.clear();
+        self.notifications.clear();
+        self.dropped_notifications = 0;
+        self.log_level = LoggingLevel::Info;
+        self.resource_cache.clear();
+        self.recent_responses.clear();
+        self.pending_requests.clear();
+        self.outgoing.clear();
+        self.initialized = false;
+        self.log_level_advertised = false;
+        self.init_params = None;
+        self.exiting = false;
+    }
+
+    fn log(&mut self, level: LoggingLevel, logger: Option<&str>, data: Value) {
+        if level >= self.log_level {
+            self.enqueue(Notification::message(level, logger, data));
+        }
+    }
+
+    // Queues a notification, evicting an older one if the buffer is full.
+    // Progress notifications are evicted first since a newer one supersedes them.
+    fn enqueue(&mut self, notification: Notification) {
+        if let Some(capacity) = self.notification_capacity {
+            self.evict_to(capacity.saturating_sub(1));
+        }
+        self.notifications.push_back(notification);
+        #[cfg(feature = "async")]
+        self.outbox_ready.notify_one();
+    }
+
+    fn outgoing_request(
+        &mut self,
+        method: &str,
+        params: Option<Value>,
+        callback: ResponseCallback,
+    ) -> Request {
+        let id = self.request_ids.next();
+        self.pending_requests.insert(id.clone(), callback);
+        Request::new(id, method, params)
+    }
+
+    fn send_request(&mut self, method: &str, params: Option<Value>, callback: ResponseCallback) {
+        let request = self.outgoing_request(method, params, callback);
+        self.outgoing.push_back(request);
+        #[cfg(feature = "async")]
+        self.outbox_ready.notify_one();
+    }
+
+    fn evict_to(&mut self, len: usize) {
+        while self.notifications.len() > len {
+            let oldest_progress = self
+                .notifications
+                .iter()
+                .position(|n| n.method == "notifications/progress");
+            self.notifications.remove(oldest_progress.unwrap_or(0));
+            self.dropped_notifications += 1;
+        }
+    }
+
+    fn recent_response(&mut self, id: &RequestId) -> Option<Response> {
+        let window = self.dedup_window?;
+        self.recent_responses
+            .retain(|_, (answered, _)| answered.elapsed() < window);
+        self.recent_responses
+            .get(id)
+            .map(|(_, response)| response.clone())
+    }
+
+    fn remember_response(&mut self, id: RequestId, response: &Response) {
+        if self.dedup_window.is_some() && id != RequestId::Null {
+            self.recent_responses
+                .insert(id, (Instant::now(), response.clone()));
+        }
+    }
+
+    fn cached_resource(&self, uri: &str) -> Option<ResourceContents> {
+        let ttl = self.resource_cache_ttl?;
+        self.resource_cache
+            .get(uri)
+            .filter(|(cached_at, _)| cached_at.elapsed() < ttl)
+            .map(|(_, contents)| contents.clone())
+    }
+
+    fn cache_resource(&mut self, uri: &str, contents: ResourceContents) {
+        if self.resource_cache_ttl.is_some() {
+            self.resource_cache
+                .insert(uri.to_string(), (Instant::now(), contents));
+        }
+    }
+
+    fn resource_updated(&mut self, uri: &str) {
+        // Changed contents invalidate the cache whether or not anyone is subscribed
+        self.resource_cache.remove(uri);
+        if !self.subscriptions.contains(uri) {
+            return;
+        }
+        self.enqueue(Notification::resource_updated(uri));
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerCapabilities {
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub logging: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompts: Option<PromptsCapability>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourcesCapability>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<ToolsCapability>,
+}
+
+/// The params of `initialize`, all of which the spec requires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeParams {
+    pub protocol_version: String,
+    pub capabilities: Value,
+    pub client_info: Implementation,
+}
+
+/// The result of `initialize`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeResult {
+    pub protocol_version: String,
+    pub capabilities: ServerCapabilities,
+    pub server_info: Implementation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Implementation {
+    name: String,
+    version: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PromptsCapability {
+    pub list_changed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ResourcesCapability {
+    pub subscribe: bool,
+    pub list_changed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ToolsCapability {
+    pub list_changed: bool,
+}
+
+const STATS_METHOD: &str = "x-mcp/stats";
+
+// Where stats count requests for methods the server doesn't dispatch
+const UNKNOWN_METHOD: &str = "unknown";
+
+// Built-in methods and the capability that has to be advertised for each
+const BUILTIN_METHODS: &[(&str, Option<Capability>)] = &[
+    ("initialize", None),
+    ("ping", None),
+    ("logging/setLevel", Some(Capability::Logging)),
+    ("resources/list", Some(Capability::Resources)),
+    ("resources/templates/list", Some(Capability::Resources)),
+    ("resources/read", Some(Capability::Resources)),
+    ("resources/subscribe", Some(Capability::Resources)),
+    ("resources/unsubscribe", Some(Capability::Resources)),
+    ("prompts/list", Some(Capability::Prompts)),
+    ("prompts/get", Some(Capability::Prompts)),
+    ("completion/complete", Some(Capability::Prompts)),
+    ("tools/list", Some(Capability::Tools)),
+    ("tools/call", Some(Capability::Tools)),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Capability {
+    Logging,
+    Prompts,
+    Resources,
+    Tools,
+}
+
+impl ServerCapabilities {
+    fn advertises(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Logging => self.logging.is_some(),
+            Capability::Prompts => self.prompts.is_some(),
+            Capability::Resources => self.resources.is_some(),
+            Capability::Tools => self.tools.is_some(),
+        }
+    }
+
+    /// Adds the capabilities `other` advertises, such as those a plugin
+    /// contributes. A capability is kept if either side has it, and each of
+    /// its flags is set if either side sets it. Logging settings already
+    /// present here win over `other`'s.
+    pub fn merge(&mut self, other: &Self) {
+        if self.logging.is_none() {
+            self.logging.clone_from(&other.logging);
+        }
+        if let Some(theirs) = &other.prompts {
+            let ours = self.prompts.get_or_insert_with(Default::default);
+            ours.list_changed |= theirs.list_changed;
+        }
+        if let Some(theirs) = &other.resources {
+            let ours = self.resources.get_or_insert_with(Default::default);
+            ours.subscribe |= theirs.subscribe;
+            ours.list_changed |= theirs.list_changed;
+        }
+        if let Some(theirs) = &other.tools {
+            let ours = self.tools.get_or_insert_with(Default::default);
+            ours.list_changed |= theirs.list_changed;
+        }
+    }
+}
+
+impl ServerCore {
+    /// Creates a core advertising every capability the crate supports.
+    pub fn new(name: &str, version: &str) -> Self {
+        Self::with_capabilities(
+            name,
+            version,
+            ServerCapabilities {
+                logging: Some(Value::Object(serde_json::Map::new())),
+                prompts: Some(PromptsCapability {
+                    list_changed: false,
+                }),
+                resources: Some(ResourcesCapability {
+                    subscribe: true,
+                    list_changed: false,
+                }),
+                tools: Some(ToolsCapability {
+                    list_changed: false,
+                }),
+            },
+        )
+    }
+
+    /// Creates a core advertising only `capabilities`. Methods belonging to a
+    /// capability that isn't advertised are rejected as not found.
+    pub fn with_capabilities(name: &str, version: &str, capabilities: ServerCapabilities) -> Self {
+        ServerCore {
+            capabilities,
+            implementation: Implementation {
+                name: name.to_string(),
+                version: version.to_string(),
+            },
+            resources: Vec::new(),
+            templates: Vec::new(),
+            prompts: Vec::new(),
+            tools: Vec::new(),
+            methods: HashMap::new(),
+            notification_handlers: HashMap::new(),
+            instructions: None,
+            param_decorator: None,
+            recommended_log_level: None,
+            page_size: None,
+            reinit_policy: ReinitPolicy::default(),
+            ping_details: false,
+            max_content_bytes: None,
+            denied_methods: HashSet::new(),
+            allowed_methods: None,
+            request_ids: IdGenerator::default(),
+        }
+    }
+
+    /// Starts a fresh connection that shares this core's registries.
+    pub fn new_session(self: &Arc<Self>) -> Session {
+        Session {
+            core: Arc::clone(self),
+            state: Arc::new(Mutex::new(ServerState {
+                request_ids: self.request_ids.clone(),
+                ..ServerState::default()
+            })),
+            #[cfg(feature = "watch")]
+            watchers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Registers a resource whose contents are produced by `reader` on
+    /// `resources/read`. Its URI must have a scheme, and is normalized: the
+    /// scheme is lowercased and `.` and `..` path segments are resolved, as
+    /// they are in the URIs clients read.
+    pub fn add_resource<F>(&mut self, resource: Resource, reader: F) -> Result<(), McpError>
+    where
+        F: Fn(&RequestContext) -> HandlerResult<ResourceContents> + Send + Sync + 'static,
+    {
+        self.resources.push(RegisteredResource {
+            resource: normalize_resource(resource)?,
+            reader: ResourceSource::Contents(Arc::new(reader)),
+        });
+        Ok(())
+    }
+
+    /// Registers a resource template. Reads of a URI matching it, and no
+    /// concrete resource, go to `reader` with the template's variables bound;
+    /// it may return several contents, e.g. the entries of a directory. The
+    /// template's URI is validated and normalized like a resource's.
+    pub fn add_resource_template<F>(
+        &mut self,
+        template: ResourceTemplate,
+        reader: F,
+    ) -> Result<(), McpError>
+    where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<Vec<ResourceContents>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.templates.push(RegisteredTemplate {
+            template: normalize_template(template)?,
+            reader: Arc::new(reader),
+        });
+        Ok(())
+    }
+
+    /// Registers a large binary resource read `chunk_size` bytes at a time.
+    /// `reader` is called with the byte offset and maximum length to read.
+    /// A `resources/read` carrying a `_meta.chunk` cursor (`"0"` for the start)
+    /// returns one chunk plus `_meta.nextChunk` while more remains; without a
+    /// cursor the whole resource is assembled and returned at once.
+    pub fn add_chunked_resource<F>(
+        &mut self,
+        resource: Resource,
+        chunk_size: usize,
+        reader: F,
+    ) -> Result<(), McpError>
+    where
+        F: Fn(u64, usize, &RequestContext) -> HandlerResult<ResourceChunk> + Send + Sync + 'static,
+    {
+        self.resources.push(RegisteredResource {
+            resource: normalize_resource(resource)?,
+            reader: ResourceSource::Chunked {
+                chunk_size: chunk_size.max(1),
+                reader: Arc::new(reader),
+            },
+        });
+        Ok(())
+    }
+
+    /// Registers a resource whose body is the stream of bytes `open` returns.
+    /// When the transport serves resource streams, such as `HttpTransport`
+    /// with `stream_resources_at`, `resources/read` answers with a
+    /// `_meta.streamUrl` reference instead of the body, which the transport
+    /// then passes to the client chunk by chunk without holding it in memory.
+    /// Otherwise the stream is drained on the calling thread and returned as
+    /// a blob.
+    #[cfg(feature = "async")]
+    pub fn add_streamed_resource<F, S>(
+        &mut self,
+        resource: Resource,
+        open: F,
+    ) -> Result<(), McpError>
+    where
+        F: Fn(&RequestContext) -> HandlerResult<S> + Send + Sync + 'static,
+        S: futures_core::Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send + 'static,
+    {
+        let open: StreamOpener = Arc::new(move |ctx| Ok(Box::pin(open(ctx)?) as ByteStream));
+        self.resources.push(RegisteredResource {
+            resource: normalize_resource(resource)?,
+            reader: ResourceSource::Streamed(open),
+        });
+        Ok(())
+    }
+
+    /// Registers a prompt whose messages are produced by `render` on `prompts/get`.
+    pub fn add_prompt<F, R>(&mut self, prompt: Prompt, render: F)
+    where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<R>
+            + Send
+            + Sync
+            + 'static,
+        R: Into<PromptResult>,
+    {
+        self.add_prompt_with_completions(prompt, render, HashMap::new());
+    }
+
+    /// Like `add_prompt`, additionally resolving `completion/complete` requests
+    /// for the prompt's arguments through `completions`, keyed by argument name.
+    pub fn add_prompt_with_completions<F, R>(
+        &mut self,
+        prompt: Prompt,
+        render: F,
+        completions: HashMap<String, Box<dyn CompletionProvider>>,
+    ) where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<R>
+            + Send
+            + Sync
+            + 'static,
+        R: Into<PromptResult>,
+    {
+        self.prompts.push(RegisteredPrompt {
+            prompt,
+            render: prompt_renderer(render),
+            completions: completions
+                .into_iter()
+                .map(|(argument, provider)| (argument, Arc::from(provider)))
+                .collect(),
+        });
+    }
+
+    /// Registers a tool invoked with the call's `arguments` on `tools/call`.
+    /// Handler errors are reported to the client as a result with `isError` set,
+    /// except an `ErrorResponse`, which is sent as a JSON-RPC error, `data` and
+    /// all.
+    pub fn register_tool<F>(&mut self, tool: Tool, handler: F)
+    where
+        F: Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync + 'static,
+    {
+        self.register_tool_with_timeout(tool, None, handler);
+    }
+
+    /// Like `register_tool`, with `timeout` replacing the session-wide request
+    /// timeout for this tool when calls go through the async path. `None` keeps
+    /// the session default.
+    pub fn register_tool_with_timeout<F>(
+        &mut self,
+        tool: Tool,
+        timeout: Option<Duration>,
+        handler: F,
+    ) where
+        F: Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync + 'static,
+    {
+        self.tools.push(RegisteredTool {
+            tool,
+            handler: Arc::new(handler),
+            structured: None,
+            timeout,
+        });
+    }
+
+    /// Registers a tool with a result renderer per `ContentPreference`: `text`
+    /// by default, `structured` for clients that ask for structured content
+    /// through `_meta.preferredContent` or declare `capabilities.content.structured`.
+    /// Structured results also carry their JSON as text for older clients.
+    pub fn register_structured_tool<F, G>(&mut self, tool: Tool, text: F, structured: G)
+    where
+        F: Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync + 'static,
+        G: Fn(Value, &RequestContext) -> HandlerResult<Value> + Send + Sync + 'static,
+    {
+        self.tools.push(RegisteredTool {
+            tool,
+            handler: Arc::new(text),
+            structured: Some(Arc::new(structured)),
+            timeout: None,
+        });
+    }
+
+    /// Registers a handler for a method outside of the MCP spec, e.g. `x-acme/status`.
+    pub fn register_method<F>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(Option<Value>, &RequestContext) -> HandlerResult<Value> + Send + Sync + 'static,
+    {
+        self.methods.insert(name.to_string(), Arc::new(handler));
+    }
+
+    /// Like `register_method`, for a handler taking positional params as in
+    /// generic JSON-RPC. Missing params are passed as an empty list and named
+    /// ones are rejected with Invalid params.
+    pub fn register_method_positional<F>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(Vec<Value>, &RequestContext) -> HandlerResult<Value> + Send + Sync + 'static,
+    {
+        self.register_method(name, move |params, ctx| match params {
+            None => handler(Vec::new(), ctx),
+            Some(Value::Array(params)) => handler(params, ctx),
+            Some(_) => Err(ErrorResponse::new(
+                ErrorCode::InvalidParams,
+                "Expected positional params",
+            )
+            .into()),
+        });
+    }
+
+    /// Registers a handler for a notification outside of the MCP spec, e.g.
+    /// `x-acme/heartbeat`. Notifications get no response, so an error is only
+    /// reported to the client as an `Error` log message.
+    pub fn register_notification<F>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(Option<Value>) -> Result<(), McpError> + Send + Sync + 'static,
+    {
+        self.notification_handlers
+            .insert(name.to_string(), Arc::new(handler));
+    }
+
+    /// Registers the `x-mcp/stats` method, which reports the session's request
+    /// counts per method, error count, and uptime. Requests for methods the
+    /// server doesn't know are counted together under `unknown`.
+    pub fn enable_stats(&mut self) {
+        self.register_method(STATS_METHOD, |_, ctx| {
+            Ok(ctx.state.lock().unwrap().stats.to_value())
+        });
+    }
+
+    /// Guidance for the client's model on how to use this server, sent as
+    /// `instructions` in the initialize result.
+    pub fn set_instructions(&mut self, instructions: impl Into<String>) {
+        self.instructions = Some(instructions.into());
+    }
+
+    /// Lets `decorator` rewrite the params of every request before it is
+    /// dispatched, e.g. to inject a default field. It gets the method name and
+    /// the params, which start as an empty object if the request had none.
+    pub fn set_param_decorator<F>(&mut self, decorator: F)
+    where
+        F: Fn(&str, &mut Value) + Send + Sync + 'static,
+    {
+        self.param_decorator = Some(Arc::new(decorator));
+    }
+
+    /// Non-standard: after `initialize` from a client that declares a `logging`
+    /// capability, sends it one informational `notifications/message`
+    /// recommending `level` as the minimum level to request.
+    pub fn advertise_min_log_level(&mut self, level: LoggingLevel) {
+        self.recommended_log_level = Some(level);
+    }
+
+    /// Chooses how a repeated `initialize` on the same session is answered.
+    pub fn set_reinit_policy(&mut self, policy: ReinitPolicy) {
+        self.reinit_policy = policy;
+    }
+
+    /// Caps the text a tool result may carry at `max` bytes in total. Longer
+    /// text is cut off with a `[truncated]` marker and the result gets
+    /// `_meta.truncated: true`.
+    pub fn set_max_content_bytes(&mut self, max: usize) {
+        self.max_content_bytes = Some(max);
+    }
+
+    /// Non-standard: answers `ping` with the server's capabilities and the
+    /// session's request stats instead of an empty object, for monitoring.
+    pub fn ping_echoes_capabilities(&mut self, enabled: bool) {
+        self.ping_details = enabled;
+    }
+
+    /// Splits `tools/list`, `resources/list`, `resources/templates/list`, and
+    /// `prompts/list` into pages of `size` entries. Each page carries
+    /// `_meta.totalCount` and, while more remain, a `nextCursor` to pass back as
+    /// `cursor`.
+    pub fn set_page_size(&mut self, size: usize) {
+        self.page_size = Some(size.max(1));
+    }
+
+    /// Answers `name` with "Method not found" even if a handler exists.
+    pub fn deny_method(&mut self, name: &str) {
+        self.denied_methods.insert(name.to_string());
+    }
+
+    /// Restricts dispatch to `names`; every other method answers "Method not
+    /// found". Denied methods stay denied.
+    pub fn allow_only(&mut self, names: &[&str]) {
+        self.allowed_methods = Some(names.iter().map(|name| name.to_string()).collect());
+    }
+
+    /// A snapshot of everything the server exposes: identity, capabilities, and
+    /// the tool, resource, and prompt descriptors.
+    pub fn describe(&self) -> Value {
+        let tools: Vec<&Tool> = self.tools.iter().map(|entry| &entry.tool).collect();
+        let resources: Vec<&Resource> =
+            self.resources.iter().map(|entry| &entry.resource).collect();
+        let prompts: Vec<&Prompt> = self.prompts.iter().map(|entry| &entry.prompt).collect();
+        serde_json::json!({
+            "serverInfo": self.implementation,
+            "capabilities": self.capabilities,
+            "tools": tools,
+            "resources": resources,
+            "prompts": prompts,
+        })
+    }
+
+    /// Every method name this server will dispatch, taking advertised capabilities
+    /// into account. Custom methods follow the built-ins in alphabetical order.
+    pub fn supported_methods(&self) -> Vec<&str> {
+        let mut custom: Vec<&str> = self
+            .methods
+            .keys()
+            .map(String::as_str)
+            .filter(|name| !BUILTIN_METHODS.iter().any(|(builtin, _)| builtin == name))
+            .collect();
+        custom.sort_unstable();
+
+        BUILTIN_METHODS
+            .iter()
+            .filter(|(_, capability)| capability.is_none_or(|c| self.capabilities.advertises(c)))
+            .map(|(name, _)| *name)
+            .chain(custom)
+            .filter(|name| self.is_permitted(name))
+            .collect()
+    }
+
+    /// The registered resource templates, in registration order, for matching
+    /// URIs or offering completions for each of their `variables`.
+    pub fn resource_templates(&self) -> Vec<&ResourceTemplate> {
+        self.templates.iter().map(|entry| &entry.template).collect()
+    }
+
+    // Why `method` is refused before dispatch, if it is
+    fn gate_reason(&self, method: &str) -> Option<&'static str> {
+        if !self.is_permitted(method) {
+            return Some("disabled");
+        }
+        BUILTIN_METHODS
+            .iter()
+            .find(|(name, _)| *name == method)
+            .and_then(|(_, capability)| *capability)
+            .filter(|&capability| !self.capabilities.advertises(capability))
+            .map(|_| "capability_not_advertised")
+    }
+
+    // The name a request for `method` is counted under. Methods that are
+    // neither built in nor registered share one bucket, so clients can't grow
+    // the counters without bound.
+    fn counted_method<'a>(&self, method: &'a str) -> &'a str {
+        let known = self.methods.contains_key(method)
+            || BUILTIN_METHODS.iter().any(|(name, _)| *name == method);
+        if known {
+            method
+        } else {
+            UNKNOWN_METHOD
+        }
+    }
+
+    fn is_permitted(&self, method: &str) -> bool {
+        !self.denied_methods.contains(method)
+            && self
+                .allowed_methods
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(method))
+    }
+}
+
+impl Server {
+    /// Creates a server advertising every capability the crate supports.
+    pub fn new(name: &str, version: &str) -> Self {
+        Self::from_core(ServerCore::new(name, version))
+    }
+
+    /// Creates a server advertising only `capabilities`. Methods belonging to a
+    /// capability that isn't advertised are rejected as not found.
+    pub fn with_capabilities(name: &str, version: &str, capabilities: ServerCapabilities) -> Self {
+        Self::from_core(ServerCore::with_capabilities(name, version, capabilities))
+    }
+
+    /// Wraps `core` together with a fresh session.
+    pub fn from_core(core: ServerCore) -> Self {
+        Server {
+            session: Arc::new(core).new_session(),
+        }
+    }
+
+    /// Classifies `message` without handling it, for routing it elsewhere. Fails
+    /// if it isn't well-formed JSON-RPC; batch entries aren't checked.
+    pub fn inspect(message: &str) -> Result<MessageInfo, McpError> {
+        let parsed: Value = serde_json::from_str(strip_bom(message))?;
+        if parsed.is_array() {
+            return Ok(MessageInfo {
+                kind: MessageKind::Batch,
+                method: None,
+                id: None,
+            });
+        }
+        if parsed.get("method").is_none() {
+            let response: Response = serde_json::from_value(parsed)?;
+            return Ok(MessageInfo {
+                kind: MessageKind::Response,
+                method: None,
+                id: Some(response.id),
+            });
+        }
+        if parsed.get("id").is_some() {
+            let request: Request = serde_json::from_value(parsed)?;
+            Ok(MessageInfo {
+                kind: MessageKind::Request,
+                method: Some(request.method),
+                id: Some(request.id),
+            })
+        } else {
+            let notification: Notification = serde_json::from_value(parsed)?;
+            Ok(MessageInfo {
+                kind: MessageKind::Notification,
+                method: Some(notification.method),
+                id: None,
+            })
+        }
+    }
+
+    // Registration is copy-on-write: if the core is already shared with other
+    // sessions, this server continues with its own copy.
+    fn core_mut(&mut self) -> &mut ServerCore {
+        Arc::make_mut(&mut self.session.core)
+    }
+
+    // Resources registered after the handshake change the list the client has seen
+    fn resources_changed(&self) {
+        if self.is_initialized() {
+            self.notify_resource_list_changed();
+        }
+    }
+
+    /// Registers a resource whose contents are produced by `reader` on
+    /// `resources/read`. Once the session is initialized, this also queues a
+    /// `notifications/resources/list_changed`, as do the other ways of adding
+    /// resources and templates.
+    pub fn add_resource<F>(&mut self, resource: Resource, reader: F) -> Result<(), McpError>
+    where
+        F: Fn(&RequestContext) -> HandlerResult<ResourceContents> + Send + Sync + 'static,
+    {
+        self.core_mut().add_resource(resource, reader)?;
+        self.resources_changed();
+        Ok(())
+    }
+
+    /// Registers a resource served in chunks; see `ServerCore::add_chunked_resource`.
+    pub fn add_chunked_resource<F>(
+        &mut self,
+        resource: Resource,
+        chunk_size: usize,
+        reader: F,
+    ) -> Result<(), McpError>
+    where
+        F: Fn(u64, usize, &RequestContext) -> HandlerResult<ResourceChunk> + Send + Sync + 'static,
+    {
+        self.core_mut()
+            .add_chunked_resource(resource, chunk_size, reader)?;
+        self.resources_changed();
+        Ok(())
+    }
+
+    /// Registers a streamed resource; see `ServerCore::add_streamed_resource`.
+    #[cfg(feature = "async")]
+    pub fn add_streamed_resource<F, S>(
+        &mut self,
+        resource: Resource,
+        open: F,
+    ) -> Result<(), McpError>
+    where
+        F: Fn(&RequestContext) -> HandlerResult<S> + Send + Sync + 'static,
+        S: futures_core::Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send + 'static,
+    {
+        self.core_mut().add_streamed_resource(resource, open)?;
+        self.resources_changed();
+        Ok(())
+    }
+
+    /// Registers a prompt whose messages are produced by `render` on `prompts/get`.
+    pub fn add_prompt<F, R>(&mut self, prompt: Prompt, render: F)
+    where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<R>
+            + Send
+            + Sync
+            + 'static,
+        R: Into<PromptResult>,
+    {
+        self.core_mut().add_prompt(prompt, render);
+    }
+
+    /// Registers a resource template; see `ServerCore::add_resource_template`.
+    pub fn add_resource_template<F>(
+        &mut self,
+        template: ResourceTemplate,
+        reader: F,
+    ) -> Result<(), McpError>
+    where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<Vec<ResourceContents>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.core_mut().add_resource_template(template, reader)?;
+        self.resources_changed();
+        Ok(())
+    }
+
+    /// Like `add_prompt`, additionally resolving `completion/complete` requests
+    /// for the prompt's arguments through `completions`, keyed by argument name.
+    pub fn add_prompt_with_completions<F, R>(
+        &mut self,
+        prompt: Prompt,
+        render: F,
+        completions: HashMap<String, Box<dyn CompletionProvider>>,
+    ) where
+        F: Fn(&HashMap<String, String>, &RequestContext) -> HandlerResult<R>
+            + Send
+            + Sync
+            + 'static,
+        R: Into<PromptResult>,
+    {
+        self.core_mut()
+            .add_prompt_with_completions(prompt, render, completions);
+    }
+
+    /// Registers a tool invoked with the call's `arguments` on `tools/call`.
+    /// Handler errors are reported to the client as a result with `isError` set,
+    /// except an `ErrorResponse`, which is sent as a JSON-RPC error, `data` and
+    /// all.
+    pub fn register_tool<F>(&mut self, tool: Tool, handler: F)
+    where
+        F: Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync + 'static,
+    {
+        self.core_mut().register_tool(tool, handler);
+    }
+
+    /// Like `register_tool`, with a per-tool timeout for the async path; see
+    /// `ServerCore::register_tool_with_timeout`.
+    pub fn register_tool_with_timeout<F>(
+        &mut self,
+        tool: Tool,
+        timeout: Option<Duration>,
+        handler: F,
+    ) where
+        F: Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync + 'static,
+    {
+        self.core_mut()
+            .register_tool_with_timeout(tool, timeout, handler);
+    }
+
+    /// Registers a tool with text and structured renderers; see
+    /// `ServerCore::register_structured_tool`.
+    pub fn register_structured_tool<F, G>(&mut self, tool: Tool, text: F, structured: G)
+    where
+        F: Fn(Value, &RequestContext) -> HandlerResult<Vec<Content>> + Send + Sync + 'static,
+        G: Fn(Value, &RequestContext) -> HandlerResult<Value> + Send + Sync + 'static,
+    {
+        self.core_mut()
+            .register_structured_tool(tool, text, structured);
+    }
+
+    /// Registers a handler for a method outside of the MCP spec, e.g. `x-acme/status`.
+    pub fn register_method<F>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(Option<Value>, &RequestContext) -> HandlerResult<Value> + Send + Sync + 'static,
+    {
+        self.core_mut().register_method(name, handler);
+    }
+
+    /// Registers a custom method taking positional params; see
+    /// `ServerCore::register_method_positional`.
+    pub fn register_method_positional<F>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(Vec<Value>, &RequestContext) -> HandlerResult<Value> + Send + Sync + 'static,
+    {
+        self.core_mut().register_method_positional(name, handler);
+    }
+
+    /// Registers a handler for a custom notification; see
+    /// `ServerCore::register_notification`.
+    pub fn register_notification<F>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(Option<Value>) -> Result<(), McpError> + Send + Sync + 'static,
+    {
+        self.core_mut().register_notification(name, handler);
+    }
+
+    /// Registers the `x-mcp/stats` method; see `ServerCore::enable_stats`.
+    pub fn enable_stats(&mut self) {
+        self.core_mut().enable_stats();
+    }
+
+    /// Disables `name`; see `ServerCore::deny_method`.
+    pub fn deny_method(&mut self, name: &str) {
+        self.core_mut().deny_method(name);
+    }
+
+    /// Restricts dispatch to `names`; see `ServerCore::allow_only`.
+    pub fn allow_only(&mut self, names: &[&str]) {
+        self.core_mut().allow_only(names);
+    }
+
+    /// Sets the initialize `instructions`; see `ServerCore::set_instructions`.
+    pub fn set_instructions(&mut self, instructions: impl Into<String>) {
+        self.core_mut().set_instructions(instructions);
+    }
+
+    /// Rewrites request params before dispatch; see `ServerCore::set_param_decorator`.
+    pub fn set_param_decorator<F>(&mut self, decorator: F)
+    where
+        F: Fn(&str, &mut Value) + Send + Sync + 'static,
+    {
+        self.core_mut().set_param_decorator(decorator);
+    }
+
+    /// Recommends a minimum log level on initialize; see
+    /// `ServerCore::advertise_min_log_level`.
+    pub fn advertise_min_log_level(&mut self, level: LoggingLevel) {
+        self.core_mut().advertise_min_log_level(level);
+    }
+
+    /// Chooses how a repeated `initialize` is answered; see `ReinitPolicy`.
+    pub fn set_reinit_policy(&mut self, policy: ReinitPolicy) {
+        self.core_mut().set_reinit_policy(policy);
+    }
+
+    /// Caps tool result text; see `ServerCore::set_max_content_bytes`.
+    pub fn set_max_content_bytes(&mut self, max: usize) {
+        self.core_mut().set_max_content_bytes(max);
+    }
+
+    /// Enriches `ping` results; see `ServerCore::ping_echoes_capabilities`.
+    pub fn ping_echoes_capabilities(&mut self, enabled: bool) {
+        self.core_mut().ping_echoes_capabilities(enabled);
+    }
+
+    /// Paginates list responses; see `ServerCore::set_page_size`.
+    pub fn set_page_size(&mut self, size: usize) {
+        self.core_mut().set_page_size(size);
+    }
+}
+
+impl Deref for Server {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.session
+    }
+}
+
+impl Deref for Session {
+    type Target = ServerCore;
+
+    fn deref(&self) -> &ServerCore {
+        &self.core
+    }
+}
+
+impl Session {
+    /// The registries this session dispatches to.
+    pub fn core(&self) -> &Arc<ServerCore> {
+        &self.core
+    }
+
+    /// Adds debugging details, such as the position of a JSON syntax error, to
+    /// the `data` of error responses.
+    pub fn set_verbose_errors(&self, verbose: bool) {
+        self.state.lock().unwrap().verbose_errors = verbose;
+    }
+
+    /// Answers a request whose id was already seen within `window` with the
+    /// earlier response instead of running its handler again, for transports
+    /// that may redeliver messages.
+    pub fn enable_dedup(&self, window: Duration) {
+        self.state.lock().unwrap().dedup_window = Some(window);
+    }
+
+    /// Caps how many resources this session may be subscribed to at once.
+    /// Subscribing beyond the cap fails until an unsubscribe frees a slot.
+    pub fn set_max_subscriptions(&self, max: usize) {
+        self.state.lock().unwrap().max_subscriptions = Some(max);
+    }
+
+    /// Caps how many entries a batch may have, 100 by default. Larger batches
+    /// are rejected as a whole with a single Invalid Request error.
+    pub fn set_max_batch_size(&self, max: usize) {
+        self.state.lock().unwrap().max_batch_size = max;
+    }
+
+    /// Checks every response to a request before it is sent: `jsonrpc` is
+    /// `"2.0"`, exactly one of `result` and `error` is present, and the id is
+    /// the request's. A violation panics in debug builds and is logged at
+    /// `Error` level otherwise. Meant for catching handler bugs in development.
+    pub fn set_validate_outgoing(&self, validate: bool) {
+        self.state.lock().unwrap().validate_outgoing = validate;
+    }
+
+    fn check_outgoing(&self, response: &Response, request_id: &Value) {
+        if !self.state.lock().unwrap().validate_outgoing {
+            return;
+        }
+        if let Some(violation) = response_violation(response, request_id) {
+            let message = format!("invalid outgoing response: {violation}");
+            if cfg!(debug_assertions) {
+                panic!("{message}");
+            }
+            self.log(LoggingLevel::Error, None, message);
+        }
+    }
+
+    // Set by transports that serve streamed resource bodies themselves
+    #[cfg(feature = "async")]
+    pub(crate) fn set_stream_endpoint(&self, endpoint: Option<String>) {
+        self.state.lock().unwrap().stream_endpoint = endpoint;
+    }
+
+    // Opens the body of the streamed resource at `uri`
+    #[cfg(feature = "async")]
+    pub(crate) fn open_resource_stream(&self, uri: &str) -> Result<ByteStream, McpError> {
+        let uri = normalize_uri(uri).unwrap_or_else(|| uri.to_string());
+        let open = self
+            .resources
+            .iter()
+            .find(|entry| entry.resource.uri == uri)
+            .and_then(|entry| match &entry.reader {
+                ResourceSource::Streamed(open) => Some(Arc::clone(open)),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                McpError::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "no streamed resource at this URI",
+                ))
+            })?;
+        let ctx = RequestContext {
+            id: RequestId::Null,
+            progress_token: None,
+            deadline: None,
+            traceparent: None,
+            cancellation: Arc::default(),
+            state: Arc::clone(&self.state),
+        };
+        open(&ctx).map_err(|err| McpError::Io(std::io::Error::other(err.to_string())))
+    }
+
+    /// Pretty-prints outgoing JSON, for debugging. Pretty output spans several
+    /// lines, so it doesn't suit newline-delimited transports such as `serve`.
+    pub fn set_pretty_output(&self, pretty: bool) {
+        self.state.lock().unwrap().pretty_output = pretty;
+    }
+
+    fn to_json<T: Serialize>(&self, message: &T) -> Result<String, serde_json::Error> {
+        if self.state.lock().unwrap().pretty_output {
+            serde_json::to_string_pretty(message)
+        } else {
+            serde_json::to_string(message)
+        }
+    }
+
+    // Serializes a reply, replacing any response that can't be serialized
+    fn reply_json(&self, reply: &Reply) -> String {
+        if let Ok(json) = self.to_json(reply) {
+            return json;
+        }
+        match reply {
+            Reply::Single(response) => self.response_json(&response.id, response),
+            Reply::Batch(responses) => {
+                let entries: Vec<String> = responses
+                    .iter()
+                    .map(|response| self.response_json(&response.id, response))
+                    .collect();
+                format!("[{}]", entries.join(","))
+            }
+        }
+    }
+
+    // Serializes the response to request `id`, substituting an internal error
+    // for that id if it fails so the client still gets a reply
+    fn response_json<T: Serialize>(&self, id: &RequestId, response: &T) -> String {
+        self.to_json(response).unwrap_or_else(|err| {
+            let fallback = Response::from_result(
+                id.clone(),
+                Err(ErrorResponse {
+                    code: ErrorCode::InternalError.as_i32(),
+                    message: format!("Failed to serialize response: {err}"),
+                    data: None,
+                }),
+            );
+            // Built only from strings and integers, so this can't fail
+            self.to_json(&fallback).unwrap_or_default()
+        })
+    }
+
+    fn write_json<W: Write, T: Serialize>(
+        &self,
+        out: &mut W,
+        message: &T,
+    ) -> Result<(), serde_json::Error> {
+        if self.state.lock().unwrap().pretty_output {
+            serde_json::to_writer_pretty(out, message)
+        } else {
+            serde_json::to_writer(out, message)
+        }
+    }
+
+    /// Whether the client has sent `notifications/initialized` on this session.
+    pub fn is_initialized(&self) -> bool {
+        self.state.lock().unwrap().initialized
+    }
+
+    /// Whether the handshake is complete: the session answered `initialize`
+    /// and the client then sent `notifications/initialized`.
+    pub fn is_ready(&self) -> bool {
+        self.state.lock().unwrap().is_ready()
+    }
+
+    /// Runs `callback` once the handshake is complete, say to connect to a
+    /// database only for clients that got that far. It runs at most once, right
+    /// away if the session is already ready, and replaces a previous callback
+    /// that hasn't run yet.
+    pub fn on_ready<F>(&self, callback: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            state.on_ready = Some(Box::new(callback));
+            state.take_ready_callback()
+        };
+        if let Some(callback) = ready {
+            callback();
+        }
+    }
+
+    /// The capabilities the client declared in `initialize`, if it has sent one.
+    pub fn client_capabilities(&self) -> Option<Value> {
+        self.state.lock().unwrap().client_capabilities()
+    }
+
+    /// Forgets everything the previous client established, so the session can
+    /// serve a new one: the handshake, subscriptions, log level, cancellations,
+    /// and queued or pending messages. Settings made through this API, such as
+    /// limits and timeouts, are kept, as are the registries.
+    pub fn reset(&self) {
+        self.state.lock().unwrap().reset();
+    }
+
+    /// Serves `resources/read` from a cache for up to `ttl` after each read,
+    /// skipping the reader. `notify_resource_updated` evicts the entry for its URI.
+    pub fn enable_resource_cache(&self, ttl: Duration) {
+        self.state.lock().unwrap().resource_cache_ttl = Some(ttl);
+    }
+
+    /// Sets the minimum level of log messages sent to the client. Defaults to `Info`.
+    pub fn set_log_level(&self, level: LoggingLevel) {
+        self.state.lock().unwrap().log_level = level;
+    }
+
+    /// Queues a `notifications/message` if `level` meets the configured log
+    /// level; `data` is sent as is, so it can be a string or a structured record.
+    pub fn log(&self, level: LoggingLevel, logger: Option<&str>, data: impl Into<Value>) {
+        self.state.lock().unwrap().log(level, logger, data.into());
+    }
+
+    /// Queues a `notifications/resources/updated` for `uri` if a client is
+    /// subscribed to it, under any spelling that normalizes the same.
+    pub fn notify_resource_updated(&self, uri: &str) {
+        if let Some(uri) = normalize_uri(uri) {
+            self.state.lock().unwrap().resource_updated(&uri);
+        }
+    }
+
+    /// Marks `uris` as possibly changed while no client was listening, e.g.
+    /// across a reconnect. The next `resources/subscribe` to each of them
+    /// queues one `notifications/resources/updated` right away, prompting
+    /// the client to read it again.
+    pub fn mark_resources_dirty(&self, uris: &[&str]) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .dirty_resources
+            .extend(uris.iter().filter_map(|uri| normalize_uri(uri)));
+    }
+
+    /// Queues a `notifications/resources/list_changed`, regardless of
+    /// subscriptions, if the server advertises `resources.listChanged`.
+    pub fn notify_resource_list_changed(&self) {
+        let advertised = self
+            .capabilities
+            .resources
+            .as_ref()
+            .is_some_and(|resources| resources.list_changed);
+        if advertised {
+            self.state
+                .lock()
+                .unwrap()
+                .enqueue(Notification::resource_list_changed());
+        }
+    }
+
+    /// Bounds the notification queue to `capacity` entries. When full, the oldest
+    /// progress notification (or failing that, the oldest notification) is dropped.
+    pub fn set_notification_buffer(&self, capacity: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.notification_capacity = Some(capacity);
+        state.evict_to(capacity);
+    }
+
+    /// Number of notifications discarded because the queue was full.
+    pub fn dropped_notifications(&self) -> u64 {
+        self.state.lock().unwrap().dropped_notifications
+    }
+
+    /// Removes and returns all queued notifications, oldest first.
+    pub fn drain_notifications(&self) -> Vec<Notification> {
+        self.state.lock().unwrap().notifications.drain(..).collect()
+    }
+
+    /// Watches the file at `path` and queues a `notifications/resources/updated`
+    /// for `uri` whenever it changes. Bursts of changes are debounced into a
+    /// single notification.
+    #[cfg(feature = "watch")]
+    pub fn watch_resource(
+        &self,
+        uri: &str,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<(), Box<dyn Error>> {
+        let uri = normalize_uri(uri)
+            .ok_or_else(|| McpError::Config(format!("resource URI `{uri}` has no scheme")))?;
+        let watcher = watch::ResourceWatcher::new(
+            uri,
+            path.as_ref(),
+            watch::DEFAULT_DEBOUNCE,
+            Arc::clone(&self.state),
+        )?;
+        self.watchers.lock().unwrap().push(watcher);
+        Ok(())
+    }
+
+    pub fn handle_message(&self, message: &str) -> Result<Option<String>, McpError> {
+        Ok(self
+            .process_message(message)?
+            .map(|reply| self.reply_json(&reply)))
+    }
+
+    /// Handles a message that the transport has already parsed, returning the
+    /// response as a `Value` so no string round trip is needed.
+    pub fn handle_value(&self, value: Value) -> Result<Option<Value>, McpError> {
+        let inbound = self
+            .is_recording()
+            .then(|| Recorded::Inbound(value.clone()));
+        let response = self.answer_value(value)?;
+        if let Some(inbound) = inbound {
+            self.record(inbound, response.clone())?;
+        }
+        Ok(response)
+    }
+
+    /// Like `handle_message`, but serializes the response directly into `out`
+    /// instead of allocating an intermediate `String`. Notifications write nothing.
+    pub fn handle_message_to_writer<W: Write>(
+        &self,
+        message: &str,
+        out: &mut W,
+    ) -> Result<(), McpError> {
+        if let Some(response) = self.process_message(message)? {
+            self.write_json(out, &response)?;
+        }
+        Ok(())
+    }
+
+    // `handle_value` without recording, for callers that record the message
+    // they are part of themselves
+    pub(crate) fn answer_value(&self, value: Value) -> Result<Option<Value>, McpError> {
+        self.process_value(value)?
+            .map(|reply| self.reply_value(&reply))
+            .transpose()
+    }
+
+    fn reply_value(&self, reply: &Reply) -> Result<Value, McpError> {
+        match serde_json::to_value(reply) {
+            Ok(value) => Ok(value),
+            Err(_) => Ok(serde_json::from_str(&self.reply_json(reply))?),
+        }
+    }
+
+    fn process_message(&self, message: &str) -> Result<Option<Reply>, McpError> {
+        let parsed = serde_json::from_str(strip_bom(message));
+        // Unparseable input is recorded as it arrived so replay reproduces it
+        let inbound = self.is_recording().then(|| match &parsed {
+            Ok(parsed) => Recorded::Inbound(Value::clone(parsed)),
+            Err(_) => Recorded::Unparsed(message.to_string()),
+        });
+        let reply = match parsed {
+            Ok(parsed) => self.process_value(parsed)?,
+            Err(err) => Some(Reply::Single(self.parse_error(&err))),
+        };
+        if let Some(inbound) = inbound {
+            let outbound = reply
+                .as_ref()
+                .map(|reply| self.reply_value(reply))
+                .transpose()?;
+            self.record(inbound, outbound)?;
+        }
+        Ok(reply)
+    }
+
+    // Malformed JSON is answered with a parse error rather than failing the call
+    fn parse_error(&self, err: &serde_json::Error) -> Response {
+        let data = self
+            .state
+            .lock()
+            .unwrap()
+            .verbose_errors
+            .then(|| serde_json::json!({ "line": err.line(), "column": err.column() }));
+        Response::from_result(
+            RequestId::Null,
+            Err(ErrorResponse {
+                code: ErrorCode::ParseError.as_i32(),
+                message: "Parse error".to_string(),
+                data,
+            }),
+        )
+    }
+
+    fn process_value(&self, parsed: Value) -> Result<Option<Reply>, McpError> {
+        match parsed {
+            Value::Array(batch) => self.process_batch(batch),
+            parsed => Ok(self.process_single(parsed)?.map(Reply::Single)),
+        }
+    }
+
+    // Batch entries are handled in order. Requests sharing an id can't be told
+    // apart by their responses, so all of them are rejected without running.
+    fn process_batch(&self, batch: Vec<Value>) -> Result<Option<Reply>, McpError> {
+        if batch.is_empty() {
+            return Ok(Some(Reply::Single(invalid_request(
+                RequestId::Null,
+                "Empty batch",
+            ))));
+        }
+        let max = self.state.lock().unwrap().max_batch_size;
+        if batch.len() > max {
+            return Ok(Some(Reply::Single(invalid_request(
+                RequestId::Null,
+                &format!("Batch exceeds the limit of {max} entries"),
+            ))));
+        }
+
+        let duplicates = duplicate_batch_ids(&batch);
+        let mut responses = Vec::new();
+        for message in batch {
+            match batch_request_id(&message) {
+                Some(id) if duplicates.contains(&id) => {
+                    responses.push(invalid_request(id, "Duplicate request id in batch"));
+                }
+                _ => responses.extend(self.process_single(message)?),
+            }
+        }
+        Ok((!responses.is_empty()).then_some(Reply::Batch(responses)))
+    }
+
+    fn process_single(&self, parsed: Value) -> Result<Option<Response>, McpError> {
+        // A bare string or number is neither a request nor a notification, and
+        // carries no id to answer with
+        if !parsed.is_object() {
+            return Ok(Some(invalid_request(RequestId::Null, "Invalid Request")));
+        }
+
+        // Responses to server-initiated requests carry an id but no method
+        if parsed.get("method").is_none()
+            && (parsed.get("result").is_some() || parsed.get("error").is_some())
+        {
+            self.handle_response(parsed)?;
+            return Ok(None);
+        }
+
+        // Handle request vs notification. A malformed one is answered on its
+        // own, so it doesn't take the rest of its batch down with it; the id is
+        // echoed when it is still usable.
+        if let Some(id) = parsed.get("id").cloned() {
+            let request = match serde_json::from_value(parsed) {
+                Ok(request) => request,
+                Err(_) => {
+                    let id = serde_json::from_value(id).unwrap_or(RequestId::Null);
+                    return Ok(Some(invalid_request(id, "Invalid Request")));
+                }
+            };
+            let response = self.handle_request(request)?;
+            self.check_outgoing(&response, &id);
+            Ok(Some(response))
+        } else {
+            match serde_json::from_value(parsed) {
+                Ok(notification) => self.handle_notification(notification)?,
+                Err(_) => return Ok(Some(invalid_request(RequestId::Null, "Invalid Request"))),
+            }
+            Ok(None)
+        }
+    }
+
+    fn handle_response(&self, message: Value) -> Result<(), McpError> {
+        let response: Response = match serde_json::from_value(message) {
+            Ok(response) => response,
+            // Responses are never answered, not even malformed ones
+            Err(err) => {
+                self.log(
+                    LoggingLevel::Debug,
+                    None,
+                    format!("dropped malformed response: {err}"),
+                );
+                return Ok(());
+            }
+        };
+        let callback = self
+            .state
+            .lock()
+            .unwrap()
+            .pending_requests
+            .remove(&response.id);
+        // The lock is released before running the callback so it may use the session
+        match callback {
+            Some(callback) => match response.error {
+                Some(error) => callback(Err(error)),
+                None => callback(Ok(response.result.unwrap_or(Value::Null))),
+            },
+            // Responses are never answered, so one to no request of ours is
+            // only worth a debug log
+            None => self.log(
+                LoggingLevel::Debug,
+                None,
+                format!("dropped response to unknown request {:?}", response.id),
+            ),
+        }
+        Ok(())
+    }
+
+    // Builds a server-initiated request and remembers `callback` until a
+    // response with the same id arrives.
+    fn outgoing_request(
+        &self,
+        method: &str,
+        params: Option<Value>,
+        callback: ResponseCallback,
+    ) -> Request {
+        self.state
+            .lock()
+            .unwrap()
+            .outgoing_request(method, params, callback)
+    }
+
+    /// Queues a server-initiated request for the serve loop to write to the
+    /// client; `on_response` runs when the client answers. A handler waiting on
+    /// the answer needs `serve_async`, which keeps reading while it waits.
+    pub fn send_request<F>(&self, method: &str, params: Option<Value>, on_response: F)
+    where
+        F: FnOnce(Result<Value, ErrorResponse>) + Send + 'static,
+    {
+        self.state
+            .lock()
+            .unwrap()
+            .send_request(method, params, Box::new(on_response));
+    }
+
+    /// Abandons the server-initiated request `id`: its callback is dropped, so
+    /// a late response is ignored, and it is unqueued if it wasn't sent yet.
+    /// Returns the `notifications/cancelled` telling the client, which the
+    /// caller sends.
+    pub fn cancel_outgoing(&self, id: RequestId) -> Notification {
+        let mut state = self.state.lock().unwrap();
+        state.pending_requests.remove(&id);
+        state.outgoing.retain(|request| request.id != id);
+        Notification::cancelled(id, None)
+    }
+
+    /// Removes and returns the server-initiated requests queued by
+    /// `send_request`, for callers driving the session without a serve loop.
+    pub fn drain_outgoing_requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().outgoing.drain(..).collect()
+    }
+
+    /// Builds an `elicitation/create` request asking the user for input matching
+    /// `requested_schema`. The caller sends the request; `on_result` runs when the
+    /// client answers.
+    pub fn request_elicitation<F>(
+        &self,
+        message: &str,
+        requested_schema: Value,
+        on_result: F,
+    ) -> Request
+    where
+        F: FnOnce(Result<ElicitationResult, ErrorResponse>) + Send + 'static,
+    {
+        let params = serde_json::json!({
+            "message": message,
+            "requestedSchema": requested_schema,
+        });
+        self.outgoing_request(
+            "elicitation/create",
+            Some(params),
+            Box::new(move |result| {
+                on_result(result.and_then(|value| {
+                    serde_json::from_value(value).map_err(|err| ErrorResponse {
+                        code: ErrorCode::InvalidParams.as_i32(),
+                        message: format!("Invalid elicitation result: {err}"),
+                        data: None,
+                    })
+                }))
+            }),
+        )
+    }
+
+    /// Builds a `sampling/createMessage` request with `params` (messages, model
+    /// preferences, and so on). The caller sends the request; `on_result` runs
+    /// when the client answers.
+    pub fn request_sampling<F>(&self, params: Value, on_result: F) -> Request
+    where
+        F: FnOnce(Result<CreateMessageResult, ErrorResponse>) + Send + 'static,
+    {
+        self.outgoing_request(
+            "sampling/createMessage",
+            Some(params),
+            Box::new(move |result| {
+                on_result(result.and_then(|value| {
+                    serde_json::from_value(value).map_err(|err| ErrorResponse {
+                        code: ErrorCode::InvalidParams.as_i32(),
+                        message: format!("Invalid sampling result: {err}"),
+                        data: None,
+                    })
+                }))
+            }),
+        )
+    }
+
+    fn handle_request(&self, mut request: Request) -> Result<Response, McpError> {
+        if let Some(decorate) = &self.param_decorator {
+            let params = request
+                .params
+                .get_or_insert_with(|| Value::Object(serde_json::Map::new()));
+            decorate(&request.method, params);
+        }
+        if let Some(response) = self.state.lock().unwrap().recent_response(&request.id) {
+            return Ok(response);
+        }
+
+        let id = request.id.clone();
+        let method = request.method.clone();
+        let started = Instant::now();
+        let cancellation = self.state.lock().unwrap().begin_request(&id);
+        // A panicking handler fails its own request rather than the whole server
+        let dispatched = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            self.dispatch_request(request, Arc::clone(&cancellation))
+        }));
+        self.state
+            .lock()
+            .unwrap()
+            .finish_request(&id, &cancellation);
+        let response = match dispatched {
+            Ok(response) => response?,
+            Err(panic) => self.panic_response(id.clone(), panic.as_ref()),
+        };
+        let mut state = self.state.lock().unwrap();
+        let error_code = response.error.as_ref().map(ErrorResponse::code);
+        let method = self.counted_method(&method);
+        state.stats.record(method, error_code, started.elapsed());
+        state.remember_response(id, &response);
+        Ok(response)
+    }
+
+    fn panic_response(&self, id: RequestId, panic: &(dyn std::any::Any + Send)) -> Response {
+        let data = self.state.lock().unwrap().verbose_errors.then(|| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            serde_json::json!({ "panic": message })
+        });
+        Response::from_result(
+            id,
+            Err(ErrorResponse {
+                code: ErrorCode::InternalError.as_i32(),
+                message: "Internal error".to_string(),
+                data,
+            }),
+        )
+    }
+
+    // In verbose mode, tells the client why its request was refused
+    fn rejection_data(&self, reason: &str) -> Option<Value> {
+        self.state
+            .lock()
+            .unwrap()
+            .verbose_errors
+            .then(|| serde_json::json!({ "reason": reason }))
+    }
+
+    fn dispatch_request(
+        &self,
+        request: Request,
+        cancellation: Arc<Cancellation>,
+    ) -> Result<Response, McpError> {
+        let ctx = RequestContext {
+            id: request.id.clone(),
+            progress_token: request
+                .params
+                .as_ref()
+                .and_then(|params| params.get("_meta"))
+                .and_then(|meta| meta.get("progressToken"))
+                .and_then(|token| serde_json::from_value(token.clone()).ok()),
+            deadline: request_deadline(request.params.as_ref()),
+            traceparent: request_traceparent(request.params.as_ref()),
+            cancellation,
+            state: Arc::clone(&self.state),
+        };
+
+        if let Some(reason) = self.gate_reason(&request.method) {
+            return Ok(Response::from_result(
+                request.id,
+                Err(ErrorResponse {
+                    code: ErrorCode::MethodNotFound.as_i32(),
+                    message: "Method not found".to_string(),
+                    data: self.rejection_data(reason),
+                }),
+            ));
+        }
+
+        // Until the handshake completes only `initialize` and `ping` are served
+        let before_handshake = !matches!(request.method.as_str(), "initialize" | "ping");
+        if before_handshake && !self.is_initialized() {
+            return Ok(Response::from_result(
+                request.id,
+                Err(ErrorResponse {
+                    code: ErrorCode::NotInitialized.as_i32(),
+                    message: "Server not initialized".to_string(),
+                    data: self.rejection_data("not_initialized"),
+                }),
+            ));
+        }
+
+        if ctx
+            .deadline
+            .is_some_and(|deadline| deadline <= SystemTime::now())
+        {
+            return Ok(timeout_response(request.id));
+        }
+
+        match request.method.as_str() {
+            "initialize" => {
+                let params = request.params.clone().unwrap_or(Value::Null);
+                if let Err(err) = InitializeParams::deserialize(&params) {
+                    return Ok(Response::from_result(
+                        request.id,
+                        Err(ErrorResponse {
+                            code: ErrorCode::InvalidParams.as_i32(),
+                            message: format!("Invalid initialize params: {err}"),
+                            data: None,
+                        }),
+                    ));
+                }
+                let ready = {
+                    let mut state = self.state.lock().unwrap();
+                    let rejection = match (&state.init_params, self.reinit_policy) {
+                        (None, _) => None,
+                        (Some(_), ReinitPolicy::Error) => Some("Already initialized"),
+                        (Some(previous), ReinitPolicy::Idempotent) if *previous != params => {
+                            Some("Already initialized with different parameters")
+                        }
+                        (Some(_), ReinitPolicy::Idempotent) => None,
+                        (Some(_), ReinitPolicy::AllowReset) => {
+                            state.reset();
+                            None
+                        }
+                    };
+                    if let Some(message) = rejection {
+                        return Ok(invalid_request(request.id, message));
+                    }
+                    state.init_params = Some(params);
+                    state.take_ready_callback()
+                };
+                if let Some(callback) = ready {
+                    callback();
+                }
+
+                let result = InitializeResult {
+                    protocol_version: PROTOCOL_VERSION.to_string(),
+                    capabilities: self.capabilities.clone(),
+                    server_info: self.implementation.clone(),
+                    instructions: self.instructions.clone(),
+                };
+                let client_logs = request
+                    .params
+                    .as_ref()
+                    .and_then(|params| params.get("capabilities"))
+                    .is_some_and(|capabilities| capabilities.get("logging").is_some());
+                if let Some(level) = self.recommended_log_level.filter(|_| client_logs) {
+                    let mut state = self.state.lock().unwrap();
+                    if !state.log_level_advertised {
+                        state.log_level_advertised = true;
+                        // Sent regardless of the current level so the advice isn't filtered out
+                        state.enqueue(Notification::message(
+                            LoggingLevel::Info,
+                            None,
+                            serde_json::json!({ "recommendedLevel": level }),
+                        ));
+                    }
+                }
+                Ok(Response::from_result(
+                    request.id,
+                    Ok(serde_json::to_value(result)?),
+                ))
+            }
+            "ping" => {
+                let result = if self.ping_details {
+                    let stats = self.state.lock().unwrap().stats.to_value();
+                    serde_json::json!({ "capabilities": self.capabilities, "stats": stats })
+                } else {
+                    Value::Object(serde_json::Map::new())
+                };
+                Ok(Response::from_result(request.id, Ok(result)))
+            }
+            "resources/list" => {
+                let resources: Vec<&Resource> =
+                    self.resources.iter().map(|entry| &entry.resource).collect();
+                Ok(self.list_page(request.id, "resources", &resources, request.params.as_ref()))
+            }
+            "logging/setLevel" => {
+                let level = request
+                    .params
+                    .as_ref()
+                    .and_then(|params| params.get("level"))
+                    .and_then(|level| LoggingLevel::deserialize(level).ok());
+                let Some(level) = level else {
+                    return Ok(Response::from_result(
+                        request.id,
+                        Err(ErrorResponse {
+                            code: ErrorCode::InvalidParams.as_i32(),
+                            message: "Missing or unknown log level".to_string(),
+                            data: None,
+                        }),
+                    ));
+                };
+                self.set_log_level(level);
+                Ok(Response::from_result(
+                    request.id,
+                    Ok(Value::Object(serde_json::Map::new())),
+                ))
+            }
+            "resources/templates/list" => {
+                let templates: Vec<&ResourceTemplate> =
+                    self.templates.iter().map(|entry| &entry.template).collect();
+                Ok(self.list_page(
+                    request.id,
+                    "resourceTemplates",
+                    &templates,
+                    request.params.as_ref(),
+                ))
+            }
+            "resources/read" => Ok(self.handle_resources_read(request.id, request.params, &ctx)),
+            "prompts/list" => {
+                let prompts: Vec<&Prompt> =
+                    self.prompts.iter().map(|entry| &entry.prompt).collect();
+                Ok(self.list_page(request.id, "prompts", &prompts, request.params.as_ref()))
+            }
+            "prompts/get" => Ok(self.handle_prompts_get(request.id, request.params, &ctx)),
+            "completion/complete" => Ok(self.handle_completion(request.id, request.params)),
+            "tools/list" => {
+                let tools: Vec<&Tool> = self.tools.iter().map(|entry| &entry.tool).collect();
+                Ok(self.list_page(request.id, "tools", &tools, request.params.as_ref()))
+            }
+            "tools/call" if !self.tools.is_empty() => {
+                Ok(self.handle_tools_call(request.id, request.params, &ctx))
+            }
+            // Tools are advertised but nothing could run one; a custom
+            // `tools/call` handler, if any, is reached below
+            "tools/call" if !self.methods.contains_key("tools/call") => Ok(Response::from_result(
+                request.id,
+                Err(ErrorResponse {
+                    code: ErrorCode::MethodNotFound.as_i32(),
+                    message: "No tools registered".to_string(),
+                    data: None,
+                }),
+            )),
+            "resources/subscribe" | "resources/unsubscribe" => {
+                let uri = request
+                    .params
+                    .as_ref()
+                    .and_then(|params| params.get("uri"))
+                    .and_then(Value::as_str);
+                let Some(uri) = uri else {
+                    return Ok(Response::from_result(
+                        request.id,
+                        Err(ErrorResponse {
+                            code: ErrorCode::InvalidParams.as_i32(),
+                            message: "Missing resource uri".to_string(),
+                            data: None,
+                        }),
+                    ));
+                };
+                // Stored normalized, so any spelling of a URI names one subscription
+                let Some(uri) = normalize_uri(uri) else {
+                    return Ok(Response::from_result(
+                        request.id,
+                        Err(ErrorResponse {
+                            code: ErrorCode::InvalidParams.as_i32(),
+                            message: "Invalid resource uri".to_string(),
+                            data: None,
+                        }),
+                    ));
+                };
+
+                let mut state = self.state.lock().unwrap();
+                if request.method == "resources/subscribe" {
+                    let at_limit = state
+                        .max_subscriptions
+                        .is_some_and(|max| state.subscriptions.len() >= max);
+                    if at_limit && !state.subscriptions.contains(&uri) {
+                        return Ok(Response::from_result(
+                            request.id,
+                            Err(ErrorResponse {
+                                code: ErrorCode::ServerError.as_i32(),
+                                message: "Too many resource subscriptions".to_string(),
+                                data: None,
+                            }),
+                        ));
+                    }
+                    if state.dirty_resources.remove(&uri) {
+                        state.subscriptions.insert(uri.clone());
+                        state.resource_updated(&uri);
+                    } else {
+                        state.subscriptions.insert(uri);
+                    }
+                } else {
+                    state.subscriptions.remove(&uri);
+                }
+                Ok(Response::from_result(
+                    request.id,
+                    Ok(Value::Object(serde_json::Map::new())),
+                ))
+            }
+            method if self.methods.contains_key(method) => {
+                match (self.methods[method])(request.params, &ctx) {
+                    Ok(result) => Ok(Response::from_result(request.id, Ok(result))),
+                    Err(err) => Ok(Response::from_result(request.id, Err(handler_error(err)))),
+                }
+            }
+            _ => {
+                let error = ErrorResponse {
+                    code: ErrorCode::MethodNotFound.as_i32(),
+                    message: "Method not found".to_string(),
+                    data: self.rejection_data("unknown"),
+                };
+                Ok(Response::from_result(request.id, Err(error)))
+            }
+        }
+    }
+
+    fn handle_resources_read(
+        &self,
+        id: RequestId,
+        params: Option<Value>,
+        ctx: &RequestContext,
+    ) -> Response {
+        // Looked up in the same canonical form resources are registered in
+        let uri = params
+            .as_ref()
+            .and_then(|params| params.get("uri"))
+            .and_then(Value::as_str)
+            .map(|uri| normalize_uri(uri).unwrap_or_else(|| uri.to_string()));
+        let uri = uri.as_deref();
+        let Some(entry) = uri.and_then(|uri| self.resources.iter().find(|r| r.resource.uri == uri))
+        else {
+            let matched = uri.and_then(|uri| {
+                self.templates
+                    .iter()
+                    .find_map(|entry| Some((entry, entry.template.match_uri(uri)?)))
+            });
+            if let Some((entry, variables)) = matched {
+                return Self::read_template(id, entry, &variables, ctx);
+            }
+            return Response::from_result(
+                id,
+                Err(ErrorResponse {
+                    code: ErrorCode::ResourceNotFound.as_i32(),
+                    message: "Resource not found".to_string(),
+                    data: None,
+                }),
+            );
+        };
+        let uri = &entry.resource.uri;
+        let reader = match &entry.reader {
+            ResourceSource::Contents(reader) => reader,
+            ResourceSource::Chunked { chunk_size, reader } => {
+                return Self::read_chunked(id, entry, *chunk_size, reader, params.as_ref(), ctx);
+            }
+            #[cfg(feature = "async")]
+            ResourceSource::Streamed(open) => return self.read_streamed(id, entry, open, ctx),
+        };
+
+        let cached = self.state.lock().unwrap().cached_resource(uri);
+        // The lock is released while reading since readers may report progress
+        let contents = match cached {
+            Some(contents) => Ok(contents),
+            None => reader(ctx).inspect(|contents| {
+                self.state
+                    .lock()
+                    .unwrap()
+                    .cache_resource(uri, contents.clone());
+            }),
+        };
+
+        match contents {
+            Ok(contents) => {
+                Response::from_result(id, Ok(serde_json::json!({ "contents": [contents] })))
+            }
+            Err(err) => Response::from_result(id, Err(handler_error(err))),
+        }
+    }
+
+    fn read_template(
+        id: RequestId,
+        entry: &RegisteredTemplate,
+        variables: &HashMap<String, String>,
+        ctx: &RequestContext,
+    ) -> Response {
+        match (entry.reader)(variables, ctx) {
+            Ok(contents) => {
+                Response::from_result(id, Ok(serde_json::json!({ "contents": contents })))
+            }
+            Err(err) => Response::from_result(id, Err(handler_error(err))),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn read_streamed(
+        &self,
+        id: RequestId,
+        entry: &RegisteredResource,
+        open: &StreamOpener,
+        ctx: &RequestContext,
+    ) -> Response {
+        let uri = &entry.resource.uri;
+        let endpoint = self.state.lock().unwrap().stream_endpoint.clone();
+        if let Some(endpoint) = endpoint {
+            // The body is fetched separately, so only point the client at it
+            let mut contents = serde_json::json!({
+                "uri": uri,
+                "_meta": { "streamUrl": format!("{endpoint}?uri={}", percent_encode(uri)) },
+            });
+            if let Some(mime_type) = &entry.resource.mime_type {
+                contents["mimeType"] = Value::from(mime_type.as_str());
+            }
+            return Response::from_result(id, Ok(serde_json::json!({ "contents": [contents] })));
+        }
+
+        let read = open(ctx).and_then(|stream| Ok(async_session::drain_stream(stream)?));
+        let data = match read {
+            Ok(data) => data,
+            Err(err) => return Response::from_result(id, Err(handler_error(err))),
+        };
+        let contents = ResourceContents::Blob {
+            uri: uri.clone(),
+            mime_type: entry.resource.mime_type.clone(),
+            blob: BASE64.encode(data),
+        };
+        Response::from_result(id, Ok(serde_json::json!({ "contents": [contents] })))
+    }
+
+    fn read_chunked(
+        id: RequestId,
+        entry: &RegisteredResource,
+        chunk_size: usize,
+        reader: &ChunkReader,
+        params: Option<&Value>,
+        ctx: &RequestContext,
+    ) -> Response {
+        let cursor = params
+            .and_then(|params| params.get("_meta"))
+            .and_then(|meta| meta.get("chunk"));
+        let start = match cursor {
+            None => None,
+            Some(cursor) => match cursor.as_str().and_then(|c| c.parse::<u64>().ok()) {
+                Some(offset) => Some(offset),
+                None => {
+                    return Response::from_result(
+                        id,
+                        Err(ErrorResponse {
+                            code: ErrorCode::InvalidParams.as_i32(),
+                            message: "Invalid chunk cursor".to_string(),
+                            data: None,
+                        }),
+                    );
+                }
+            },
+        };
+
+        let mut data = Vec::new();
+        let mut offset = start.unwrap_or(0);
+        let mut meta = None;
+        loop {
+            let chunk = match reader(offset, chunk_size, ctx) {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    return Response::from_result(id, Err(handler_error(err)));
+                }
+            };
+            let read = chunk.data.len() as u64;
+            data.extend(chunk.data);
+            offset += read;
+            let more = read > 0 && offset < chunk.total_size;
+            if let Some(start) = start {
+                // Paged reads return a single chunk, where it sits in the
+                // resource, and a cursor for the next one
+                let mut page = serde_json::json!({
+                    "totalSize": chunk.total_size,
+                    "chunkOffset": start,
+                });
+                if more {
+                    page["nextChunk"] = Value::from(offset.to_string());
+                }
+                meta = Some(page);
+                break;
+            }
+            if !more {
+                break;
+            }
+        }
+
+        let contents = ResourceContents::Blob {
+            uri: entry.resource.uri.clone(),
+            mime_type: entry.resource.mime_type.clone(),
+            blob: BASE64.encode(data),
+        };
+        let mut result = serde_json::json!({ "contents": [contents] });
+        if let Some(meta) = meta {
+            result["_meta"] = meta;
+        }
+        Response::from_result(id, Ok(result))
+    }
+
+    // Answers a list request with the `key` entries, one page at a time if a
+    // page size is set. The cursor is the decimal offset of the page.
+    fn list_page<T: Serialize>(
+        &self,
+        id: RequestId,
+        key: &str,
+        items: &[T],
+        params: Option<&Value>,
+    ) -> Response {
+        let cursor = params.and_then(|params| params.get("cursor"));
+        let mut result = serde_json::Map::new();
+        let Some(page_size) = self.page_size else {
+            result.insert(key.to_string(), serde_json::json!(items));
+            return Response::from_result(id, Ok(Value::Object(result)));
+        };
+
+        let start = match cursor {
+            None => 0,
+            Some(cursor) => match cursor.as_str().and_then(|c| c.parse::<usize>().ok()) {
+                Some(start) if start <= items.len() => start,
+                _ => {
+                    return Response::from_result(
+                        id,
+                        Err(ErrorResponse {
+                            code: ErrorCode::InvalidParams.as_i32(),
+                            message: "Invalid cursor".to_string(),
+                            data: None,
+                        }),
+                    );
+                }
+            },
+        };
+        let end = (start + page_size).min(items.len());
+        result.insert(key.to_string(), serde_json::json!(&items[start..end]));
+        result.insert(
+            "_meta".to_string(),
+            serde_json::json!({ "totalCount": items.len() }),
+        );
+        if end < items.len() {
+            result.insert("nextCursor".to_string(), Value::String(end.to_string()));
+        }
+        Response::from_result(id, Ok(Value::Object(result)))
+    }
+
+    fn handle_tools_call(
+        &self,
+        id: RequestId,
+        params: Option<Value>,
+        ctx: &RequestContext,
+    ) -> Response {
+        let name = params
+            .as_ref()
+            .and_then(|params| params.get("name"))
+            .and_then(Value::as_str);
+        let Some(entry) = name.and_then(|name| self.tools.iter().find(|t| t.tool.name == name))
+        else {
+            return Response::from_result(
+                id,
+                Err(ErrorResponse {
+                    code: ErrorCode::InvalidParams.as_i32(),
+                    message: "Unknown tool".to_string(),
+                    data: None,
+                }),
+            );
+        };
+
+        let arguments = params
+            .as_ref()
+            .and_then(|params| params.get("arguments"))
+            .cloned()
+            .unwrap_or_else(|| Value::Object(serde_json::Map::new()));
+
+        let preference = content_preference(params.as_ref(), self.client_capabilities());
+        let outcome = match &entry.structured {
+            Some(structured) if preference == ContentPreference::Structured => {
+                structured(arguments, ctx).map(|value| {
+                    let text = vec![Content::Text {
+                        text: value.to_string(),
+                    }];
+                    (text, Some(value))
+                })
+            }
+            _ => (entry.handler)(arguments, ctx).map(|content| (content, None)),
+        };
+
+        // Tool failures are part of the result so the model can see and react to them
+        let result = match outcome {
+            Ok((mut content, structured)) => {
+                let truncated = self
+                    .max_content_bytes
+                    .is_some_and(|max| truncate_text(&mut content, max));
+                let mut result = serde_json::json!({ "content": content, "isError": false });
+                if let Some(structured) = structured {
+                    result["structuredContent"] = structured;
+                }
+                if truncated {
+                    result["_meta"] = serde_json::json!({ "truncated": true });
+                }
+                result
+            }
+            // Unless the handler asked for a protocol error
+            Err(err) => match err.downcast::<ErrorResponse>() {
+                Ok(error) => return Response::from_result(id, Err(*error)),
+                Err(err) => serde_json::json!({
+                    "content": [Content::Text { text: err.to_string() }],
+                    "isError": true,
+                }),
+            },
+        };
+        Response::from_result(id, Ok(result))
+    }
+
+    fn handle_prompts_get(
+        &self,
+        id: RequestId,
+        params: Option<Value>,
+        ctx: &RequestContext,
+    ) -> Response {
+        let name = params
+            .as_ref()
+            .and_then(|params| params.get("name"))
+            .and_then(Value::as_str);
+        let Some(entry) = name.and_then(|name| self.prompts.iter().find(|p| p.prompt.name == name))
+        else {
+            return Response::from_result(
+                id,
+                Err(ErrorResponse {
+                    code: ErrorCode::InvalidParams.as_i32(),
+                    message: "Unknown prompt".to_string(),
+                    data: None,
+                }),
+            );
+        };
+
+        let arguments = params
+            .as_ref()
+            .and_then(|params| params.get("arguments"))
+            .cloned()
+            .map(serde_json::from_value::<HashMap<String, String>>)
+            .unwrap_or_else(|| Ok(HashMap::new()));
+        let arguments = match arguments {
+            Ok(arguments) => arguments,
+            Err(err) => {
+                return Response::from_result(
+                    id,
+                    Err(ErrorResponse {
+                        code: ErrorCode::InvalidParams.as_i32(),
+                        message: format!("Invalid prompt arguments: {err}"),
+                        data: None,
+                    }),
+                );
+            }
+        };
+
+        // Unknown arguments are passed through; only missing required ones are rejected
+        let missing: Vec<&str> = entry
+            .prompt
+            .arguments
+            .iter()
+            .filter(|argument| argument.required && !arguments.contains_key(&argument.name))
+            .map(|argument| argument.name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Response::from_result(
+                id,
+                Err(ErrorResponse {
+                    code: ErrorCode::InvalidParams.as_i32(),
+                    message: format!("Missing required arguments: {}", missing.join(", ")),
+                    data: Some(serde_json::json!({ "missing": missing })),
+                }),
+            );
+        }
+
+        match (entry.render)(&arguments, ctx) {
+            Ok(mut result) => {
+                if result.description.is_none() {
+                    result.description.clone_from(&entry.prompt.description);
+                }
+                let result = serde_json::to_value(result).map_err(|err| handler_error(err.into()));
+                Response::from_result(id, result)
+            }
+            Err(err) => Response::from_result(id, Err(handler_error(err))),
+        }
+    }
+
+    fn handle_completion(&self, id: RequestId, params: Option<Value>) -> Response {
+        let params = params.unwrap_or(Value::Null);
+        let reference = &params["ref"];
+        let argument_name = params["argument"]["name"].as_str().unwrap_or_default();
+        let argument_value = params["argument"]["value"].as_str().unwrap_or_default();
+        // Non-string values can't have been chosen from completions; skip them
+        let context: HashMap<String, String> = params["context"]["arguments"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+            .collect();
+
+        let mut values = Vec::new();
+        if reference["type"] == "ref/prompt" {
+            let name = reference["name"].as_str();
+            let Some(entry) =
+                name.and_then(|name| self.prompts.iter().find(|p| p.prompt.name == name))
+            else {
+                return Response::from_result(
+                    id,
+                    Err(ErrorResponse {
+                        code: ErrorCode::InvalidParams.as_i32(),
+                        message: "Unknown prompt".to_string(),
+                        data: None,
+                    }),
+                );
+            };
+            // Arguments without a provider simply have nothing to suggest
+            if let Some(provider) = entry.completions.get(argument_name) {
+                values = provider.complete_labeled(argument_value, &context);
+            }
+        }
+
+        let total = values.len();
+        values.truncate(MAX_COMPLETION_VALUES);
+        Response::from_result(
+            id,
+            Ok(serde_json::json!({
+                "completion": {
+                    "values": values,
+                    "total": total,
+                    "hasMore": total > MAX_COMPLETION_VALUES,
+                }
+            })),
+        )
+    }
+
+    fn handle_notification(&self, notification: Notification) -> Result<(), McpError> {
+        match notification.method.as_str() {
+            "notifications/initialized" => {
+                let ready = {
+                    let mut state = self.state.lock().unwrap();
+                    // Only completes a handshake that `initialize` started
+                    if state.init_params.is_none() {
+                        return Ok(());
+                    }
+                    state.initialized = true;
+                    state.take_ready_callback()
+                };
+                if let Some(callback) = ready {
+                    callback();
+                }
+                Ok(())
+            }
+            "notifications/cancelled" => {
+                let params: CancelledParams = notification
+                    .params
+                    .and_then(|params| serde_json::from_value(params).ok())
+                    .unwrap_or_default();
+                // A request that already finished, or never existed, is
+                // ignored. In the async path the cancelled one is never answered.
+                let state = self.state.lock().unwrap();
+                if let Some(cancellation) =
+                    params.request_id.and_then(|id| state.in_flight.get(&id))
+                {
+                    cancellation.cancel();
+                }
+                Ok(())
+            }
+            // Progress is only meaningful for server-initiated requests, of which
+            // there are none yet, so it is accepted whatever its params look like
+            "notifications/progress" => Ok(()),
+            "notifications/exit" => {
+                // Release subscriptions and watches before the serve loop winds down
+                #[cfg(feature = "watch")]
+                self.watchers.lock().unwrap().clear();
+                let mut state = self.state.lock().unwrap();
+                state.subscriptions.clear();
+                state.exiting = true;
+                Ok(())
+            }
+            method => {
+                if let Some(handler) = self.notification_handlers.get(method) {
+                    if let Err(err) = handler(notification.params) {
+                        self.log(LoggingLevel::Error, Some(method), err.to_string());
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the client has asked the server to exit.
+    pub fn should_exit(&self) -> bool {
+        self.state.lock().unwrap().exiting
+    }
+
+    /// Runs a newline-delimited JSON-RPC loop, writing each response followed by
+    /// any queued notifications. Returns when `input` is exhausted or after the
+    /// client sends `notifications/exit`, flushing pending output first. Only
+    /// I/O errors end the loop early, including a message over
+    /// `DEFAULT_MAX_MESSAGE_SIZE` bytes. See `serve_transport`.
+    pub fn serve<R: BufRead, W: Write>(&self, input: R, output: W) -> Result<(), Box<dyn Error>> {
+        let mut transport = FramedTransport::new(input, output, Framing::NewlineDelimited);
+        Ok(self.serve_transport(&mut transport)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Completes the lifecycle handshake so requests aren't rejected
+    pub(crate) fn initialized(server: Server) -> Server {
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "initialize",
+            "params": initialize_params(serde_json::json!({}))
+        });
+        server.handle_value(initialize).unwrap();
+        server
+            .handle_message(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+            .unwrap();
+        server
+    }
+
+    // Valid `initialize` params declaring `capabilities`
+    pub(crate) fn initialize_params(capabilities: Value) -> Value {
+        serde_json::json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": capabilities,
+            "clientInfo": { "name": "test-client", "version": "1.0.0" }
+        })
+    }
+
+    #[test]
+    fn test_initialization() {
+        let server = Server::new("test-server", "1.0.0");
+
+        // Test initialize request
+        let init_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2024-11-05",
+                "capabilities": {},
+                "clientInfo": {
+                    "name": "test-client",
+                    "version": "1.0.0"
+                }
+            }
+        });
+
+        let response = server
+            .handle_message(&init_request.to_string())
+            .unwrap()
+            .unwrap();
+        let response_value: Value = serde_json::from_str(&response).unwrap();
+
+        assert_eq!(response_value["result"]["protocolVersion"], "2024-11-05");
+        assert!(response_value["result"]["capabilities"].is_object());
+    }
+
+    #[test]
+    fn test_on_ready() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let server = Server::new("test-server", "1.0.0");
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        server.on_ready(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": initialize_params(serde_json::json!({}))
+        });
+        server.handle_value(initialize).unwrap();
+        assert!(!server.is_ready());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let server = initialized(server);
+        assert!(server.is_ready());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A repeated notification doesn't run it again
+        initialized(server);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_requests_before_initialized() {
+        let server = Server::new("test-server", "1.0.0");
+        let call = |method: &str| {
+            let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method });
+            server.handle_value(request).unwrap().unwrap()
+        };
+
+        assert_eq!(call("tools/list")["error"]["code"], -32003);
+        assert!(call("ping").get("error").is_none());
+        // `notifications/initialized` doesn't count before `initialize`
+        server
+            .handle_message(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+            .unwrap();
+        assert!(!server.is_initialized());
+        assert_eq!(call("tools/list")["error"]["code"], -32003);
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": initialize_params(serde_json::json!({}))
+        });
+        let response = server.handle_value(initialize).unwrap().unwrap();
+        assert!(response.get("error").is_none());
+
+        server
+            .handle_message(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+            .unwrap();
+        assert!(call("tools/list")["result"]["tools"].is_array());
+    }
+
+    #[test]
+    fn test_leading_bom_is_ignored() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let ping = r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#;
+
+        let response = server.handle_message(&format!("\u{FEFF}{ping}")).unwrap();
+        let response: Value = serde_json::from_str(&response.unwrap()).unwrap();
+        assert!(response["result"].is_object());
+
+        let response = server
+            .handle_message(&format!("\u{FEFF}\u{FEFF}{ping}"))
+            .unwrap();
+        let response: Value = serde_json::from_str(&response.unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], -32700);
+    }
+
+    #[test]
+    fn test_duplicate_request_is_replayed() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_method("x-test/increment", move |_, _| {
+            Ok(Value::from(counter.fetch_add(1, Ordering::SeqCst) + 1))
+        });
+        server.enable_dedup(Duration::from_secs(60));
+
+        let request =
+            serde_json::json!({ "jsonrpc": "2.0", "id": 9, "method": "x-test/increment" });
+        let first = server.handle_value(request.clone()).unwrap().unwrap();
+        let second = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(first, second);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let request =
+            serde_json::json!({ "jsonrpc": "2.0", "id": 10, "method": "x-test/increment" });
+        assert_eq!(server.handle_value(request).unwrap().unwrap()["result"], 2);
+    }
+
+    #[test]
+    fn test_template_read_returns_all_contents() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server
+            .add_resource_template(
+                ResourceTemplate {
+                    uri_template: "file:///dir/{name}".to_string(),
+                    name: "directory".to_string(),
+                    description: None,
+                    mime_type: None,
+                },
+                |variables, _| {
+                    let dir = &variables["name"];
+                    Ok(["a.txt", "b.txt"]
+                        .iter()
+                        .map(|file| ResourceContents::Text {
+                            uri: format!("file:///dir/{dir}/{file}"),
+                            mime_type: None,
+                            text: file.to_string(),
+                        })
+                        .collect())
+                },
+            )
+            .unwrap();
+
+        let request =
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "resources/templates/list" });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(
+            response["result"]["resourceTemplates"][0]["uriTemplate"],
+            "file:///dir/{name}"
+        );
+
+        let read = |uri: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "resources/read",
+                "params": { "uri": uri }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+        let response = read("file:///dir/notes");
+        let contents = response["result"]["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0]["uri"], "file:///dir/notes/a.txt");
+        assert_eq!(contents[1]["text"], "b.txt");
+
+        assert_eq!(read("file:///dir/a/b")["error"]["code"], -32002);
+    }
+
+    #[test]
+    fn test_serialization_failure_fallback() {
+        let server = Server::new("test-server", "1.0.0");
+        // JSON object keys must be strings, so this map can't be serialized
+        let unserializable = HashMap::from([(vec![1u8], 1)]);
+        let json = server.response_json(&RequestId::Number(7), &unserializable);
+        let response: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["error"]["code"], -32603);
+        assert!(response.get("result").is_none());
+    }
+
+    #[test]
+    fn test_list_resource_templates() {
+        let mut server = Server::new("test-server", "1.0.0");
+        server
+            .add_resource_template(
+                ResourceTemplate {
+                    uri_template: "logs://{service}/{date}.log".to_string(),
+                    name: "daily logs".to_string(),
+                    description: None,
+                    mime_type: None,
+                },
+                |_, _| Ok(vec![]),
+            )
+            .unwrap();
+
+        let templates = server.resource_templates();
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates[0].name, "daily logs");
+        assert_eq!(templates[0].variables(), ["service", "date"]);
+    }
+
+    #[test]
+    fn test_template_with_several_variables() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        let template = ResourceTemplate {
+            uri_template: "file:///{project}/{file}".to_string(),
+            name: "project files".to_string(),
+            description: None,
+            mime_type: None,
+        };
+        server
+            .add_resource_template(template.clone(), |variables, _| {
+                Ok(vec![ResourceContents::Text {
+                    uri: format!("file:///{}/{}", variables["project"], variables["file"]),
+                    mime_type: None,
+                    text: format!("{} in {}", variables["file"], variables["project"]),
+                }])
+            })
+            .unwrap();
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "resources/read",
+            "params": { "uri": "file:///alpha/main.rs" }
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(
+            response["result"]["contents"][0]["text"],
+            "main.rs in alpha"
+        );
+
+        let variables = template.match_uri("file:///my%20app/main.rs").unwrap();
+        assert_eq!(variables["project"], "my app");
+        assert_eq!(variables["file"], "main.rs");
+        for uri in [
+            "file:///alpha/a%2Fb.rs",
+            "file:///alpha/a%5Cb.rs",
+            "file:///alpha/a%00b.rs",
+        ] {
+            assert_eq!(template.match_uri(uri), None, "{uri}");
+        }
+
+        for uri in [
+            "file:///alpha",
+            "file:///alpha/src/main.rs",
+            "file:///alpha/",
+            "http:///alpha/main.rs",
+            "file:///alpha/bad%zz",
+        ] {
+            assert_eq!(template.match_uri(uri), None, "{uri}");
+        }
+    }
+
+    #[test]
+    fn test_client_supports_images() {
+        let mut server = Server::new("test-server", "1.0.0");
+        server.register_tool(
+            Tool {
+                name: "chart".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |_, ctx| {
+                Ok(vec![if ctx.client_supports_images() {
+                    Content::Image {
+                        data: "iVBORw0KGgo=".to_string(),
+                        mime_type: "image/png".to_string(),
+                    }
+                } else {
+                    Content::Text {
+                        text: "up and to the right".to_string(),
+                    }
+                }])
+            },
+        );
+        let chart_type = |capabilities: Value| {
+            server.reset();
+            let initialize = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": initialize_params(capabilities.clone())
+            });
+            server.handle_value(initialize).unwrap();
+            assert_eq!(server.client_capabilities(), Some(capabilities));
+            server
+                .handle_message(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+                .unwrap();
+            let call = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "tools/call",
+                "params": { "name": "chart" }
+            });
+            server.handle_value(call).unwrap().unwrap()["result"]["content"][0]["type"].clone()
+        };
+
+        assert_eq!(chart_type(serde_json::json!({})), "image");
+        assert_eq!(
+            chart_type(serde_json::json!({ "content": { "image": {}, "text": {} } })),
+            "image"
+        );
+        assert_eq!(
+            chart_type(serde_json::json!({ "content": { "text": {} } })),
+            "text"
+        );
+    }
+
+    #[test]
+    fn test_max_content_bytes() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(
+            Tool {
+                name: "dump".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |arguments, _| {
+                let size = arguments["size"].as_u64().unwrap() as usize;
+                Ok(vec![
+                    Content::Text {
+                        text: "é".repeat(size),
+                    },
+                    Content::Text {
+                        text: "more".to_string(),
+                    },
+                ])
+            },
+        );
+        server.set_max_content_bytes(11);
+        let call = |size: u64| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "dump", "arguments": { "size": size } }
+            });
+            server.handle_value(request).unwrap().unwrap()["result"].clone()
+        };
+
+        let result = call(3);
+        assert_eq!(result["content"].as_array().unwrap().len(), 2);
+        assert!(result.get("_meta").is_none());
+
+        // The cut lands on a character boundary and the rest of the text goes
+        let result = call(100);
+        let content = result["content"].as_array().unwrap();
+        assert_eq!(content.len(), 1);
+        assert_eq!(content[0]["text"], format!("ééééé{TRUNCATION_MARKER}"));
+        assert_eq!(result["_meta"]["truncated"], true);
+    }
+
+    #[test]
+    fn test_rejection_reasons() {
+        let mut server = Server::with_capabilities(
+            "test-server",
+            "1.0.0",
+            ServerCapabilities {
+                tools: Some(ToolsCapability::default()),
+                ..ServerCapabilities::default()
+            },
+        );
+        server.deny_method("tools/list");
+        let reason = |method: &str| {
+            let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method });
+            server.handle_value(request).unwrap().unwrap()["error"]["data"]["reason"].clone()
+        };
+
+        // Reasons are only given in verbose mode
+        assert_eq!(reason("tools/call"), Value::Null);
+        server.set_verbose_errors(true);
+        assert_eq!(reason("tools/call"), "not_initialized");
+        let server = initialized(server);
+        let reason = |method: &str| {
+            let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method });
+            server.handle_value(request).unwrap().unwrap()["error"]["data"]["reason"].clone()
+        };
+        assert_eq!(reason("x-acme/nothing"), "unknown");
+        assert_eq!(reason("tools/list"), "disabled");
+        assert_eq!(reason("prompts/list"), "capability_not_advertised");
+    }
+
+    #[test]
+    fn test_handler_error_data() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(
+            Tool {
+                name: "weather".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |_, _| {
+                let error = ErrorResponse::new(ErrorCode::InvalidParams, "Unknown city")
+                    .with_data(serde_json::json!({ "field": "city" }));
+                Err(error.into())
+            },
+        );
+        server
+            .add_resource(
+                Resource {
+                    uri: "file:///missing".to_string(),
+                    name: "missing".to_string(),
+                    description: None,
+                    mime_type: None,
+                    size: None,
+                    annotations: None,
+                },
+                |_| {
+                    let error = ErrorResponse::new(ErrorCode::ResourceNotFound, "Gone")
+                        .with_data(serde_json::json!({ "uri": "file:///missing" }));
+                    Err(error.into())
+                },
+            )
+            .unwrap();
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "weather" }
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert!(response.get("result").is_none());
+        assert_eq!(response["error"]["code"], -32602);
+        assert_eq!(response["error"]["message"], "Unknown city");
+        assert_eq!(
+            response["error"]["data"],
+            serde_json::json!({ "field": "city" })
+        );
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "resources/read",
+            "params": { "uri": "file:///missing" }
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(response["error"]["code"], -32002);
+        assert_eq!(response["error"]["data"]["uri"], "file:///missing");
+    }
+
+    #[test]
+    fn test_panicking_tool() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(
+            Tool {
+                name: "explode".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |_, _| panic!("kaboom"),
+        );
+        let call = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "explode" }
+        });
+
+        let response = server.handle_value(call.clone()).unwrap().unwrap();
+        assert_eq!(response["error"]["code"], -32603);
+        assert_eq!(response["error"]["message"], "Internal error");
+        assert!(response["error"].get("data").is_none());
+
+        // The server keeps working, and verbose mode reveals the panic message
+        server.set_verbose_errors(true);
+        let response = server.handle_value(call).unwrap().unwrap();
+        assert_eq!(response["error"]["data"]["panic"], "kaboom");
+        let ping = serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" });
+        assert!(server.handle_value(ping).unwrap().unwrap()["result"].is_object());
+    }
+
+    #[cfg(feature = "arbitrary_precision")]
+    #[test]
+    fn test_arbitrary_precision_numbers() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(
+            Tool {
+                name: "echo".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |arguments, _| {
+                Ok(vec![Content::Text {
+                    text: arguments.to_string(),
+                }])
+            },
+        );
+
+        let call = r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"echo","arguments":{"sum":0.30000000000000000001,"big":123456789012345678901234567890}}}"#;
+        let response: Value =
+            serde_json::from_str(&server.handle_message(call).unwrap().unwrap()).unwrap();
+        assert_eq!(
+            response["result"]["content"][0]["text"],
+            r#"{"big":123456789012345678901234567890,"sum":0.30000000000000000001}"#
+        );
+    }
+
+    #[test]
+    fn test_response_from_result() {
+        let ok = Response::from_result(RequestId::Number(1), Ok(serde_json::json!({ "a": 1 })));
+        assert_eq!(
+            serde_json::to_value(&ok).unwrap(),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": { "a": 1 } })
+        );
+
+        let error = ErrorResponse {
+            code: ErrorCode::InvalidParams.as_i32(),
+            message: "Bad".to_string(),
+            data: None,
+        };
+        let err = Response::from_result(RequestId::String("x".to_string()), Err(error));
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": "x",
+                "error": { "code": -32602, "message": "Bad" }
+            })
+        );
+    }
+
+    #[test]
+    fn test_request_builder() {
+        let server = Server::new("test-server", "1.0.0");
+        let request = Request::new(
+            1,
+            "initialize",
+            Some(initialize_params(serde_json::json!({}))),
+        );
+        assert_eq!(request.id(), &RequestId::Number(1));
+
+        let response = server
+            .handle_message(&serde_json::to_string(&request).unwrap())
+            .unwrap()
+            .unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["jsonrpc"], "2.0");
+        assert_eq!(response["result"]["protocolVersion"], PROTOCOL_VERSION);
+
+        let initialized = Notification::new("notifications/initialized", None);
+        let message = serde_json::to_string(&initialized).unwrap();
+        assert!(server.handle_message(&message).unwrap().is_none());
+        assert!(server.is_initialized());
+    }
+
+    #[test]
+    fn test_inspect() {
+        let info = Server::inspect(r#"{"jsonrpc":"2.0","id":3,"method":"tools/list"}"#).unwrap();
+        assert_eq!(info.kind, MessageKind::Request);
+        assert_eq!(info.method.as_deref(), Some("tools/list"));
+        assert_eq!(info.id, Some(RequestId::Number(3)));
+
+        let info =
+            Server::inspect(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#).unwrap();
+        assert_eq!(info.kind, MessageKind::Notification);
+        assert_eq!(info.method.as_deref(), Some("notifications/initialized"));
+        assert_eq!(info.id, None);
+
+        let info = Server::inspect(r#"{"jsonrpc":"2.0","id":"a","result":{}}"#).unwrap();
+        assert_eq!(info.kind, MessageKind::Response);
+        assert_eq!(info.method, None);
+        assert_eq!(info.id, Some(RequestId::String("a".to_string())));
+
+        let info = Server::inspect(r#"[{"jsonrpc":"2.0","id":1,"method":"ping"}]"#).unwrap();
+        assert_eq!(info.kind, MessageKind::Batch);
+
+        assert!(Server::inspect("{not json").is_err());
+        assert!(Server::inspect(r#"{"jsonrpc":"2.0","id":1,"method":7}"#).is_err());
+    }
+
+    #[test]
+    fn test_paginated_tools_list() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        for name in ["a", "b", "c"] {
+            server.register_tool(
+                Tool {
+                    name: name.to_string(),
+                    description: None,
+                    input_schema: serde_json::json!({ "type": "object" }),
+                    annotations: None,
+                    meta: None,
+                },
+                |_, _| Ok(vec![]),
+            );
+        }
+        server.set_page_size(2);
+        let list = |params: Value| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/list",
+                "params": params
+            });
+            server.handle_value(request).unwrap().unwrap()["result"].clone()
+        };
+
+        let first = list(serde_json::json!({}));
+        assert_eq!(first["tools"].as_array().unwrap().len(), 2);
+        assert_eq!(first["_meta"], serde_json::json!({ "totalCount": 3 }));
+        assert_eq!(first["nextCursor"], "2");
+
+        let last = list(serde_json::json!({ "cursor": first["nextCursor"] }));
+        assert_eq!(last["tools"][0]["name"], "c");
+        assert_eq!(last["_meta"]["totalCount"], 3);
+        assert!(last.get("nextCursor").is_none());
+    }
+
+    #[test]
+    fn test_no_tools_registered() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let request = |id: i64, method: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": { "name": "anything" }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+
+        assert_eq!(
+            request(1, "tools/list")["result"]["tools"],
+            serde_json::json!([])
+        );
+        let response = request(2, "tools/call");
+        assert_eq!(response["error"]["code"], -32601);
+        assert_eq!(response["error"]["message"], "No tools registered");
+    }
+
+    #[test]
+    fn test_logging_set_level() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let set_level = |level: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "logging/setLevel",
+                "params": { "level": level }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+
+        assert_eq!(set_level("warning")["result"], serde_json::json!({}));
+        server.log(LoggingLevel::Info, None, "filtered");
+        server.log(LoggingLevel::Error, None, "kept");
+        let notifications = server.drain_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].params().unwrap()["data"], "kept");
+
+        assert_eq!(set_level("loud")["error"]["code"], -32602);
+    }
+
+    #[test]
+    fn test_log_data_payloads() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        server.log(LoggingLevel::Info, None, "plain message");
+        server.log(
+            LoggingLevel::Info,
+            Some("db"),
+            serde_json::json!({ "query": "SELECT 1", "elapsedMs": 12 }),
+        );
+
+        let notifications = server.drain_notifications();
+        assert_eq!(notifications[0].params().unwrap()["data"], "plain message");
+        assert_eq!(
+            notifications[1].params().unwrap()["data"],
+            serde_json::json!({ "query": "SELECT 1", "elapsedMs": 12 })
+        );
+    }
+
+    #[test]
+    fn test_param_decorator() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(
+            Tool {
+                name: "whoami".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |arguments, _| {
+                Ok(vec![Content::Text {
+                    text: arguments["tenant"].as_str().unwrap_or("none").to_string(),
+                }])
+            },
+        );
+        server.set_param_decorator(|method, params| {
+            if method == "tools/call" && params["arguments"].get("tenant").is_none() {
+                params["arguments"]["tenant"] = Value::from("x");
+            }
+        });
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "whoami" }
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(response["result"]["content"][0]["text"], "x");
+    }
+
+    #[test]
+    fn test_advertise_min_log_level() {
+        let initialize = |capabilities: Value| {
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": initialize_params(capabilities)
+            })
+        };
+
+        let mut server = Server::new("test-server", "1.0.0");
+        server.advertise_min_log_level(LoggingLevel::Warning);
+        server
+            .handle_value(initialize(serde_json::json!({})))
+            .unwrap();
+        assert!(server.drain_notifications().is_empty());
+
+        // A reconnecting client doesn't get the recommendation again
+        server.reset();
+        server.set_reinit_policy(ReinitPolicy::Idempotent);
+        let logging = serde_json::json!({ "logging": {} });
+        server.handle_value(initialize(logging.clone())).unwrap();
+        server.handle_value(initialize(logging)).unwrap();
+        let notifications = server.drain_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].method(), "notifications/message");
+        assert_eq!(
+            notifications[0].params().unwrap()["data"]["recommendedLevel"],
+            "warning"
+        );
+    }
+
+    #[test]
+    fn test_batch() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let batch = serde_json::json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "ping" },
+            { "jsonrpc": "2.0", "method": "notifications/initialized" },
+            { "jsonrpc": "2.0", "id": 2, "method": "tools/list" }
+        ]);
+        let responses = server.handle_value(batch).unwrap().unwrap();
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(responses.as_array().unwrap().len(), 2);
+
+        let response = server.handle_value(serde_json::json!([])).unwrap().unwrap();
+        assert_eq!(response["error"]["code"], -32600);
+    }
+
+    #[test]
+    fn test_batch_duplicate_ids() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_method("x-test/count", move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Value::Null)
+        });
+
+        let batch = serde_json::json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "x-test/count" },
+            { "jsonrpc": "2.0", "id": 2, "method": "ping" },
+            { "jsonrpc": "2.0", "id": 1, "method": "x-test/count" }
+        ]);
+        let responses = server.handle_value(batch).unwrap().unwrap();
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["error"]["code"], -32600);
+        assert!(responses[1]["result"].is_object());
+        assert_eq!(responses[2]["error"]["code"], -32600);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_ping() {
+        let server = Server::new("test-server", "1.0.0");
+
+        let ping_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "ping"
+        });
+
+        let response = server
+            .handle_message(&ping_request.to_string())
+            .unwrap()
+            .unwrap();
+        let response_value: Value = serde_json::from_str(&response).unwrap();
+
+        assert!(response_value["result"].is_object());
+        assert!(response_value["error"].is_null());
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        let noon = SystemTime::UNIX_EPOCH + Duration::from_secs(1_738_324_800);
+        assert_eq!(parse_rfc3339("2025-01-31T12:00:00Z"), Some(noon));
+        assert_eq!(parse_rfc3339("2025-01-31T13:30:00+01:30"), Some(noon));
+        assert_eq!(
+            parse_rfc3339("2025-01-31t12:00:00.25z"),
+            Some(noon + Duration::from_millis(250))
+        );
+        for invalid in ["2025-01-31", "2025-13-01T00:00:00Z", "2025-01-31T12:00:00"] {
+            assert_eq!(parse_rfc3339(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_request_deadline() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&calls);
+        server.register_tool(
+            Tool {
+                name: "work".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            move |_, ctx| {
+                seen.lock().unwrap().push(ctx.deadline());
+                Ok(vec![])
+            },
+        );
+        let call = |deadline: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "work", "_meta": { "deadline": deadline } }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+
+        let response = call("2999-01-01T00:00:00Z");
+        assert_eq!(response["result"]["isError"], false);
+        assert_eq!(
+            calls.lock().unwrap().as_slice(),
+            [parse_rfc3339("2999-01-01T00:00:00Z")]
+        );
+
+        // An expired deadline fails without running the handler
+        let response = call("2000-01-01T00:00:00Z");
+        assert_eq!(response["error"]["code"], -32001);
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_custom_notification() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        let beats = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&beats);
+        server.register_notification("x-acme/heartbeat", move |params| {
+            let params = params.unwrap_or_default();
+            seen.lock().unwrap().push(params["seq"].clone());
+            if params["seq"] == 2 {
+                return Err(McpError::Config("heartbeat out of order".to_string()));
+            }
+            Ok(())
+        });
+
+        for seq in [1, 2] {
+            let heartbeat = serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "x-acme/heartbeat",
+                "params": { "seq": seq }
+            });
+            assert!(server.handle_value(heartbeat).unwrap().is_none());
+        }
+        assert_eq!(
+            beats.lock().unwrap().as_slice(),
+            [serde_json::json!(1), serde_json::json!(2)]
+        );
+        let notifications = server.drain_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(
+            notifications[0].params().unwrap()["logger"],
+            "x-acme/heartbeat"
+        );
+    }
+
+    #[test]
+    fn test_merge_capabilities() {
+        let mut capabilities = ServerCapabilities {
+            prompts: Some(PromptsCapability::default()),
+            ..ServerCapabilities::default()
+        };
+        capabilities.merge(&ServerCapabilities {
+            prompts: Some(PromptsCapability { list_changed: true }),
+            tools: Some(ToolsCapability::default()),
+            ..ServerCapabilities::default()
+        });
+        assert_eq!(
+            capabilities,
+            ServerCapabilities {
+                prompts: Some(PromptsCapability { list_changed: true }),
+                tools: Some(ToolsCapability::default()),
+                ..ServerCapabilities::default()
+            }
+        );
+
+        // Flags are never cleared by a side that doesn't set them
+        let merged = capabilities.clone();
+        capabilities.merge(&ServerCapabilities::default());
+        assert_eq!(capabilities, merged);
+    }
+
+    #[test]
+    fn test_ping_echoes_capabilities() {
+        let mut server = Server::new("test-server", "1.0.0");
+        let ping = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" });
+        let response = server.handle_value(ping.clone()).unwrap().unwrap();
+        assert_eq!(response["result"], serde_json::json!({}));
+
+        server.ping_echoes_capabilities(true);
+        let response = server.handle_value(ping).unwrap().unwrap();
+        assert_eq!(
+            response["result"]["capabilities"],
+            serde_json::to_value(&server.capabilities).unwrap()
+        );
+        assert_eq!(response["result"]["stats"]["totalRequests"], 1);
+    }
+
+    #[test]
+    fn test_handle_message_to_writer() {
+        let server = Server::new("test-server", "1.0.0");
+
+        let ping_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "ping"
+        })
+        .to_string();
+
+        let mut out = Vec::new();
+        server
+            .handle_message_to_writer(&ping_request, &mut out)
+            .unwrap();
+        let expected = server.handle_message(&ping_request).unwrap().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        let initialized = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/initialized"
+        })
+        .to_string();
+
+        let mut out = Vec::new();
+        server
+            .handle_message_to_writer(&initialized, &mut out)
+            .unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_resource_subscription() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+
+        // Not subscribed yet, so nothing is queued
+        server.notify_resource_updated("file:///a.txt");
+        assert!(server.drain_notifications().is_empty());
+
+        let subscribe = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "resources/subscribe",
+            "params": { "uri": "file:///a.txt" }
+        });
+        let response = server
+            .handle_message(&subscribe.to_string())
+            .unwrap()
+            .unwrap();
+        let response_value: Value = serde_json::from_str(&response).unwrap();
+        assert!(response_value["result"].is_object());
+
+        server.notify_resource_updated("file:///a.txt");
+        let notifications = server.drain_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].method, "notifications/resources/updated");
+        assert_eq!(
+            notifications[0].params.as_ref().unwrap()["uri"],
+            "file:///a.txt"
+        );
+
+        let unsubscribe = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "resources/unsubscribe",
+            "params": { "uri": "file:///a.txt" }
+        });
+        server.handle_message(&unsubscribe.to_string()).unwrap();
+        server.notify_resource_updated("file:///a.txt");
+        assert!(server.drain_notifications().is_empty());
+    }
+
+    #[test]
+    fn test_dirty_resource_resync() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        server.mark_resources_dirty(&["file:///a.txt"]);
+        assert!(server.drain_notifications().is_empty());
+
+        let subscribe = |id: i64, uri: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "resources/subscribe",
+                "params": { "uri": uri }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+        subscribe(1, "file:///b.txt");
+        assert!(server.drain_notifications().is_empty());
+
+        subscribe(2, "file:///a.txt");
+        let notifications = server.drain_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].method, "notifications/resources/updated");
+        assert_eq!(notifications[0].params().unwrap()["uri"], "file:///a.txt");
+
+        // Only the first subscribe after marking resyncs
+        subscribe(3, "file:///a.txt");
+        assert!(server.drain_notifications().is_empty());
+    }
+
+    #[test]
+    fn test_subscriptions_use_normalized_uris() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let call = |method: &str, uri: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": method,
+                "params": { "uri": uri }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+        let updated = || -> Vec<Value> {
+            server
+                .drain_notifications()
+                .iter()
+                .map(|notification| notification.params().unwrap()["uri"].clone())
+                .collect()
+        };
+
+        call("resources/subscribe", "FILE:///docs/./a.txt");
+        server.notify_resource_updated("file:///docs/sub/../a.txt");
+        assert_eq!(updated(), ["file:///docs/a.txt"]);
+
+        server.mark_resources_dirty(&["File:///docs/./b.txt"]);
+        call("resources/subscribe", "file:///docs/b.txt");
+        assert_eq!(updated(), ["file:///docs/b.txt"]);
+
+        call("resources/unsubscribe", "file:///docs/x/../a.txt");
+        server.notify_resource_updated("file:///docs/a.txt");
+        assert!(updated().is_empty());
+
+        let response = call("resources/subscribe", "no scheme");
+        assert_eq!(response["error"]["code"], -32602);
+
+        let mut server = server;
+        let template = |uri_template: &str| ResourceTemplate {
+            uri_template: uri_template.to_string(),
+            name: "files".to_string(),
+            description: None,
+            mime_type: None,
+        };
+        assert!(server
+            .add_resource_template(template("files/{name}"), |_, _| Ok(vec![]))
+            .is_err());
+        server
+            .add_resource_template(template("FILE:///x/./{name}"), |_, _| Ok(vec![]))
+            .unwrap();
+        assert_eq!(
+            server.templates[0].template.uri_template,
+            "file:///x/{name}"
+        );
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn test_watch_resource_debounces_updates() {
+        use std::time::Duration;
+
+        let dir = std::env::temp_dir().join(format!("mcp_rs_watch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("watched.txt");
+        std::fs::write(&path, "initial").unwrap();
+
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let subscribe = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "resources/subscribe",
+            "params": { "uri": "file:///watched.txt" }
+        });
+        server.handle_message(&subscribe.to_string()).unwrap();
+        server.watch_resource("file:///watched.txt", &path).unwrap();
+
+        for i in 0..5 {
+            std::fs::write(&path, format!("edit {i}")).unwrap();
+        }
+        std::thread::sleep(watch::DEFAULT_DEBOUNCE * 5 + Duration::from_millis(500));
+
+        let notifications = server.drain_notifications();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].method, "notifications/resources/updated");
+    }
+
+    #[test]
+    fn test_resource_read_reports_progress() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server
+            .add_resource(
+                Resource {
+                    uri: "file:///slow.txt".to_string(),
+                    name: "slow".to_string(),
+                    description: None,
+                    mime_type: Some("text/plain".to_string()),
+                    size: None,
+                    annotations: None,
+                },
+                |ctx| {
+                    if let Some(progress) = ctx.progress() {
+                        progress.report(50.0, Some(100.0), None);
+                    }
+                    Ok(ResourceContents::Text {
+                        uri: "file:///slow.txt".to_string(),
+                        mime_type: Some("text/plain".to_string()),
+                        text: "done".to_string(),
+                    })
+                },
+            )
+            .unwrap();
+
+        let read_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "resources/read",
+            "params": {
+                "uri": "file:///slow.txt",
+                "_meta": { "progressToken": "read-1" }
+            }
+        });
+        let response = server
+            .handle_message(&read_request.to_string())
+            .unwrap()
+            .unwrap();
+        let response_value: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response_value["result"]["contents"][0]["text"], "done");
+
+        let notifications = server.drain_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].method, "notifications/progress");
+        let params = notifications[0].params.as_ref().unwrap();
+        assert_eq!(params["progressToken"], "read-1");
+        assert_eq!(params["progress"], 50.0);
+        assert_eq!(params["total"], 100.0);
+    }
+
+    #[test]
+    fn test_exit_notification_stops_serve_loop() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let input = [
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "resources/subscribe",
+                "params": { "uri": "file:///a.txt" }
+            }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "notifications/exit" }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" }),
+        ]
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+        let mut output = Vec::new();
+        server.serve(input.as_bytes(), &mut output).unwrap();
+
+        assert!(server.should_exit());
+        // The ping after exit is never answered
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        // Subscriptions were cleaned up on exit
+        server.notify_resource_updated("file:///a.txt");
+        assert!(server.drain_notifications().is_empty());
+    }
+
+    #[test]
+    fn test_serve_answers_invalid_requests() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let input = [
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1 }),
+            serde_json::json!({}),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" }),
+        ]
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+        let mut output = Vec::new();
+        server.serve(input.as_bytes(), &mut output).unwrap();
+
+        let responses: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["error"]["code"], -32600);
+        assert_eq!(responses[1]["id"], Value::Null);
+        assert_eq!(responses[1]["error"]["code"], -32600);
+        assert_eq!(responses[2]["id"], 2);
+        assert!(responses[2]["result"].is_object());
+    }
+
+    #[test]
+    fn test_notification_constructors() {
+        let progress: Value = serde_json::to_value(Notification::progress(
+            RequestId::String("tok".to_string()),
+            1.0,
+            Some(4.0),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(progress["jsonrpc"], "2.0");
+        assert_eq!(progress["method"], "notifications/progress");
+        assert_eq!(
+            progress["params"],
+            serde_json::json!({ "progressToken": "tok", "progress": 1.0, "total": 4.0 })
+        );
+
+        let progress: Value = serde_json::to_value(Notification::progress(
+            RequestId::Number(3),
+            2.0,
+            None,
+            Some("Indexing files"),
+        ))
+        .unwrap();
+        assert_eq!(
+            progress["params"],
+            serde_json::json!({ "progressToken": 3, "progress": 2.0, "message": "Indexing files" })
+        );
+
+        let updated: Value =
+            serde_json::to_value(Notification::resource_updated("file:///a.txt")).unwrap();
+        assert_eq!(updated["method"], "notifications/resources/updated");
+        assert_eq!(
+            updated["params"],
+            serde_json::json!({ "uri": "file:///a.txt" })
+        );
+
+        let message: Value = serde_json::to_value(Notification::message(
+            LoggingLevel::Warning,
+            Some("db"),
+            "slow query",
+        ))
+        .unwrap();
+        assert_eq!(message["method"], "notifications/message");
+        assert_eq!(
+            message["params"],
+            serde_json::json!({ "level": "warning", "logger": "db", "data": "slow query" })
+        );
+
+        let cancelled: Value = serde_json::to_value(Notification::cancelled(
+            RequestId::Number(3),
+            Some("timed out"),
+        ))
+        .unwrap();
+        assert_eq!(cancelled["method"], "notifications/cancelled");
+        assert_eq!(
+            cancelled["params"],
+            serde_json::json!({ "requestId": 3, "reason": "timed out" })
+        );
+
+        let bare: Value = serde_json::to_value(Notification::new("x-acme/ping", None)).unwrap();
+        assert_eq!(bare["method"], "x-acme/ping");
+        assert!(bare.get("params").is_none());
+    }
+
+    #[test]
+    fn test_handle_value() {
+        let server = Server::new("test-server", "1.0.0");
+
+        let ping_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "abc",
+            "method": "ping"
+        });
+        let response = server.handle_value(ping_request).unwrap().unwrap();
+        assert_eq!(response["id"], "abc");
+        assert!(response["result"].is_object());
+
+        let initialized = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/initialized"
+        });
+        assert!(server.handle_value(initialized).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_prompt_argument_completion() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        let prompt = Prompt {
+            name: "greet".to_string(),
+            description: None,
+            arguments: vec![
+                PromptArgument {
+                    name: "language".to_string(),
+                    description: None,
+                    required: true,
+                },
+                PromptArgument {
+                    name: "name".to_string(),
+                    description: None,
+                    required: false,
+                },
+            ],
+        };
+        let mut completions: HashMap<String, Box<dyn CompletionProvider>> = HashMap::new();
+        completions.insert(
+            "language".to_string(),
+            Box::new(|prefix: &str| {
+                ["python", "perl", "rust"]
+                    .iter()
+                    .filter(|lang| lang.starts_with(prefix))
+                    .map(ToString::to_string)
+                    .collect()
+            }),
+        );
+        server.add_prompt_with_completions(prompt, |_, _| Ok(Vec::new()), completions);
+
+        let complete = |argument: &str, value: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "completion/complete",
+                "params": {
+                    "ref": { "type": "ref/prompt", "name": "greet" },
+                    "argument": { "name": argument, "value": value }
+                }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+
+        let response = complete("language", "p");
+        assert_eq!(
+            response["result"]["completion"]["values"],
+            serde_json::json!(["python", "perl"])
+        );
+
+        let response = complete("name", "a");
+        assert!(response["error"].is_null());
+        assert_eq!(
+            response["result"]["completion"]["values"],
+            serde_json::json!([])
+        );
+    }
+
+    #[test]
+    fn test_completion_value_serde() {
+        let values = vec![
+            CompletionValue::from("py"),
+            CompletionValue::labeled("rs", "Rust"),
+        ];
+        let json = serde_json::to_value(&values).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!(["py", { "value": "rs", "label": "Rust" }])
+        );
+        let parsed: Vec<CompletionValue> = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, values);
+        assert_eq!(parsed[1].value(), "rs");
+
+        let provider = LabeledCompletion(|_: &str| vec![CompletionValue::labeled("rs", "Rust")]);
+        assert_eq!(provider.complete(""), ["rs"]);
+    }
+
+    #[test]
+    fn test_completion_context() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        let prompt = Prompt {
+            name: "travel".to_string(),
+            description: None,
+            arguments: vec![
+                PromptArgument {
+                    name: "country".to_string(),
+                    description: None,
+                    required: true,
+                },
+                PromptArgument {
+                    name: "region".to_string(),
+                    description: None,
+                    required: true,
+                },
+            ],
+        };
+        let mut completions: HashMap<String, Box<dyn CompletionProvider>> = HashMap::new();
+        completions.insert(
+            "region".to_string(),
+            Box::new(ContextualCompletion(
+                |prefix: &str, arguments: &HashMap<String, String>| {
+                    let regions: &[&str] = match arguments.get("country").map(String::as_str) {
+                        Some("fr") => &["Bretagne", "Bourgogne", "Occitanie"],
+                        Some("us") => &["Bay Area", "Boston"],
+                        _ => &[],
+                    };
+                    regions
+                        .iter()
+                        .filter(|region| region.starts_with(prefix))
+                        .map(ToString::to_string)
+                        .collect()
+                },
+            )),
+        );
+        server.add_prompt_with_completions(prompt, |_, _| Ok(Vec::new()), completions);
+
+        let complete = |context: Value| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "completion/complete",
+                "params": {
+                    "ref": { "type": "ref/prompt", "name": "travel" },
+                    "argument": { "name": "region", "value": "B" },
+                    "context": context
+                }
+            });
+            server.handle_value(request).unwrap().unwrap()["result"]["completion"]["values"].clone()
+        };
+
+        assert_eq!(
+            complete(serde_json::json!({ "arguments": { "country": "fr" } })),
+            serde_json::json!(["Bretagne", "Bourgogne"])
+        );
+        assert_eq!(
+            complete(serde_json::json!({ "arguments": { "country": "us" } })),
+            serde_json::json!(["Bay Area", "Boston"])
+        );
+        assert_eq!(complete(Value::Null), serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_bounded_notification_buffer() {
+        let server = Server::new("test-server", "1.0.0");
+        server.set_notification_buffer(2);
+
+        let ctx = RequestContext {
+            id: RequestId::Number(1),
+            progress_token: Some(RequestId::Number(1)),
+            deadline: None,
+            traceparent: None,
+            cancellation: Arc::default(),
+            state: Arc::clone(&server.state),
+        };
+        let progress = ctx.progress().unwrap();
+        for step in 1..=4 {
+            progress.report(f64::from(step), Some(4.0), None);
+        }
+
+        let notifications = server.drain_notifications();
+        assert_eq!(server.dropped_notifications(), 2);
+        let steps: Vec<&Value> = notifications
+            .iter()
+            .map(|n| &n.params.as_ref().unwrap()["progress"])
+            .collect();
+        assert_eq!(steps, [3.0, 4.0]);
+    }
+
+    #[test]
+    fn test_positional_method() {
+        let mut server = Server::new("test-server", "1.0.0");
+        server.register_method_positional("x-test/add", |params, _| {
+            Ok(params.iter().filter_map(Value::as_i64).sum::<i64>().into())
+        });
+        let server = initialized(server);
+        let call = |params: Value| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "x-test/add",
+                "params": params
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+
+        assert_eq!(call(serde_json::json!([1, 2]))["result"], 3);
+        assert_eq!(call(serde_json::json!({ "a": 1 }))["error"]["code"], -32602);
+    }
+
+    #[test]
+    fn test_supported_methods_respect_capabilities() {
+        let mut server = Server::with_capabilities(
+            "test-server",
+            "1.0.0",
+            ServerCapabilities {
+                tools: Some(ToolsCapability::default()),
+                ..ServerCapabilities::default()
+            },
+        );
+        server.register_method("x-acme/status", |_, _| Ok(Value::Null));
+
+        let methods = server.supported_methods();
+        assert_eq!(
+            methods,
+            [
+                "initialize",
+                "ping",
+                "tools/list",
+                "tools/call",
+                "x-acme/status"
+            ]
+        );
+        assert!(!methods.contains(&"prompts/list"));
+
+        // Methods of capabilities that aren't advertised are not dispatched
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "prompts/list"
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(response["error"]["code"], -32601);
+    }
+
+    #[test]
+    fn test_tools_call() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(
+            Tool {
+                name: "echo".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |arguments, _| {
+                Ok(vec![Content::Text {
+                    text: arguments["message"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                }])
+            },
+        );
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "echo", "arguments": { "message": "hi" } }
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(
+            response["result"],
+            serde_json::json!({
+                "content": [{ "type": "text", "text": "hi" }],
+                "isError": false
+            })
+        );
+    }
+
+    #[test]
+    fn test_resource_cache() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let reads = Arc::new(AtomicUsize::new(0));
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        let counter = Arc::clone(&reads);
+        server
+            .add_resource(
+                Resource {
+                    uri: "file:///config.json".to_string(),
+                    name: "config".to_string(),
+                    description: None,
+                    mime_type: None,
+                    size: None,
+                    annotations: None,
+                },
+                move |_| {
+                    let n = counter.fetch_add(1, Ordering::SeqCst);
+                    Ok(ResourceContents::Text {
+                        uri: "file:///config.json".to_string(),
+                        mime_type: None,
+                        text: format!("version {n}"),
+                    })
+                },
+            )
+            .unwrap();
+        server.enable_resource_cache(Duration::from_secs(60));
+
+        let read = || {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "resources/read",
+                "params": { "uri": "file:///config.json" }
+            });
+            server.handle_value(request).unwrap().unwrap()["result"]["contents"][0]["text"].clone()
+        };
+
+        assert_eq!(read(), "version 0");
+        assert_eq!(read(), "version 0");
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+        server.notify_resource_updated("file:///config.json");
+        assert_eq!(read(), "version 1");
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_log_from_tool_handler() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.set_log_level(LoggingLevel::Info);
+        server.register_tool(
+            Tool {
+                name: "work".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |_, ctx| {
+                ctx.log(LoggingLevel::Debug, "too chatty");
+                ctx.log(LoggingLevel::Info, "working");
+                Ok(Vec::new())
+            },
+        );
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "work" }
+        });
+        server.handle_value(request).unwrap();
+
+        let notifications = server.drain_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].method, "notifications/message");
+        assert_eq!(
+            notifications[0].params,
+            Some(serde_json::json!({ "level": "info", "data": "working" }))
+        );
+    }
+
+    #[test]
+    fn test_tools_call_mixed_content_order() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(
+            Tool {
+                name: "chart".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |_, _| {
+                Ok(vec![
+                    Content::Text {
+                        text: "before".to_string(),
+                    },
+                    Content::Image {
+                        data: "aGVsbG8=".to_string(),
+                        mime_type: "image/png".to_string(),
+                    },
+                    Content::Text {
+                        text: "after".to_string(),
+                    },
+                ])
+            },
+        );
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "chart" }
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(
+            response["result"]["content"],
+            serde_json::json!([
+                { "type": "text", "text": "before" },
+                { "type": "image", "data": "aGVsbG8=", "mimeType": "image/png" },
+                { "type": "text", "text": "after" }
+            ])
+        );
+
+        let content: Vec<Content> =
+            serde_json::from_value(response["result"]["content"].clone()).unwrap();
+        assert!(matches!(
+            content.as_slice(),
+            [
+                Content::Text { .. },
+                Content::Image { .. },
+                Content::Text { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = ServerConfig::from_json(serde_json::json!({
+            "name": "configured",
+            "version": "2.0.0",
+            "capabilities": { "tools": { "listChanged": false } },
+            "tools": [{
+                "name": "add",
+                "description": "Adds two numbers",
+                "inputSchema": { "type": "object" }
+            }],
+            "resources": [{ "uri": "file:///readme.md", "name": "readme" }],
+            "prompts": [{ "name": "summarize" }]
+        }))
+        .unwrap();
+        let mut server = Server::from_config(config).unwrap();
+        server
+            .bind_tool("add", |_, _| {
+                Ok(vec![Content::Text {
+                    text: "3".to_string(),
+                }])
+            })
+            .unwrap();
+        assert!(server.bind_tool("missing", |_, _| Ok(Vec::new())).is_err());
+
+        let exported = serde_json::to_value(server.export_config()).unwrap();
+        let reimported = Server::from_config(ServerConfig::from_json(exported).unwrap()).unwrap();
+        assert_eq!(reimported.describe(), server.describe());
+        assert_eq!(server.describe()["serverInfo"]["name"], "configured");
+        assert_eq!(server.describe()["tools"][0]["name"], "add");
+    }
+
+    fn described_tool(name: &str) -> Tool {
+        Tool {
+            name: name.to_string(),
+            description: None,
+            input_schema: serde_json::json!({ "type": "object" }),
+            annotations: None,
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn test_uncorrelated_response_is_dropped() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        server.set_log_level(LoggingLevel::Debug);
+        let response = r#"{"jsonrpc":"2.0","id":99,"result":{}}"#;
+        assert_eq!(server.handle_message(response).unwrap(), None);
+
+        let notifications = server.drain_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].params.as_ref().unwrap()["level"], "debug");
+    }
+
+    #[test]
+    fn test_max_batch_size() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        server.set_max_batch_size(3);
+        let batch = |len: i64| {
+            let pings: Vec<Value> = (1..=len)
+                .map(|id| serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "ping" }))
+                .collect();
+            server.handle_value(Value::Array(pings)).unwrap().unwrap()
+        };
+
+        assert_eq!(batch(3).as_array().unwrap().len(), 3);
+        let rejected = batch(4);
+        assert_eq!(rejected["error"]["code"], -32600);
+        assert_eq!(rejected["id"], Value::Null);
+    }
+
+    #[test]
+    fn test_batch_with_non_object_element() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let batch = serde_json::json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "ping" },
+            42,
+            { "jsonrpc": "2.0", "method": "notifications/roots/list_changed" }
+        ]);
+        let response = server.handle_value(batch).unwrap().unwrap();
+        let entries = response.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["id"], 1);
+        assert!(entries[0]["result"].is_object());
+        assert_eq!(entries[1]["id"], Value::Null);
+        assert_eq!(entries[1]["error"]["code"], -32600);
+    }
+
+    #[test]
+    fn test_batch_with_malformed_elements() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let batch = serde_json::json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "ping" },
+            { "jsonrpc": "2.0", "id": 2 },
+            { "foo": 1 },
+            { "jsonrpc": "2.0", "id": 1.5, "method": "ping" },
+            { "jsonrpc": "2.0", "id": 3, "method": "ping" }
+        ]);
+        let response = server.handle_value(batch).unwrap().unwrap();
+        let entries = response.as_array().unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0]["id"], 1);
+        assert!(entries[0]["result"].is_object());
+        assert_eq!(entries[1]["id"], 2);
+        assert_eq!(entries[1]["error"]["code"], -32600);
+        assert_eq!(entries[2]["id"], Value::Null);
+        assert_eq!(entries[2]["error"]["code"], -32600);
+        assert_eq!(entries[3]["id"], Value::Null);
+        assert_eq!(entries[3]["error"]["code"], -32600);
+        assert_eq!(entries[4]["id"], 3);
+        assert!(entries[4]["result"].is_object());
+    }
+
+    #[test]
+    fn test_register_tools() {
+        let mut server = Server::new("test-server", "1.0.0");
+        server
+            .register_tools(["add", "sub", "mul"].map(described_tool))
+            .unwrap();
+        server
+            .bind_tool("mul", |_, _| {
+                Ok(vec![Content::Text {
+                    text: "6".to_string(),
+                }])
+            })
+            .unwrap();
+        assert_eq!(server.export_config().tools.len(), 3);
+
+        let server = initialized(server);
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "mul" }
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(response["result"]["content"][0]["text"], "6");
+    }
+
+    #[test]
+    fn test_register_tools_rejects_duplicates() {
+        let mut server = Server::new("test-server", "1.0.0");
+        server.register_tools([described_tool("add")]).unwrap();
+
+        let err = server
+            .register_tools([described_tool("sub"), described_tool("add")])
+            .unwrap_err();
+        assert!(err.to_string().contains("tool `add`"), "{err}");
+        let err = server
+            .register_tools([described_tool("mul"), described_tool("mul")])
+            .unwrap_err();
+        assert!(err.to_string().contains("tool `mul`"), "{err}");
+        // A failed call registers none of its tools
+        assert_eq!(server.export_config().tools.len(), 1);
+    }
+
+    #[test]
+    fn test_cancelled_notification_with_missing_params() {
+        let server = Server::new("test-server", "1.0.0");
+
+        for notification in [
+            serde_json::json!({ "jsonrpc": "2.0", "method": "notifications/cancelled" }),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/cancelled",
+                "params": { "reason": "user aborted" }
+            }),
+        ] {
+            assert!(server.handle_value(notification).unwrap().is_none());
+        }
+
+        let id = RequestId::Number(5);
+        let cancellation = server.state.lock().unwrap().begin_request(&id);
+        let cancelled = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": { "requestId": 5 }
+        });
+        server.handle_value(cancelled.clone()).unwrap();
+        let ctx = RequestContext {
+            id: id.clone(),
+            progress_token: None,
+            deadline: None,
+            traceparent: None,
+            cancellation: Arc::clone(&cancellation),
+            state: Arc::clone(&server.state),
+        };
+        assert!(ctx.is_cancelled());
+
+        // A finished request is forgotten, and cancelling it again is a no-op
+        let mut state = server.state.lock().unwrap();
+        state.finish_request(&id, &cancellation);
+        assert!(state.in_flight.is_empty());
+        drop(state);
+        server.handle_value(cancelled).unwrap();
+        assert!(server.state.lock().unwrap().in_flight.is_empty());
+    }
+
+    #[test]
+    fn test_progress_notification_with_missing_params() {
+        let server = Server::new("test-server", "1.0.0");
+
+        for notification in [
+            serde_json::json!({ "jsonrpc": "2.0", "method": "notifications/progress" }),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/progress",
+                "params": { "progress": 10 }
+            }),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/progress",
+                "params": { "progressToken": "abc" }
+            }),
+        ] {
+            assert!(server.handle_value(notification).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_sessions_share_core() {
+        let mut core = ServerCore::new("test-server", "1.0.0");
+        core.register_tool(
+            Tool {
+                name: "echo".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |_, _| Ok(Vec::new()),
+        );
+        let core = Arc::new(core);
+        let first = core.new_session();
+        let second = core.new_session();
+
+        assert!(Arc::ptr_eq(first.core(), second.core()));
+        assert_eq!(first.describe()["tools"], second.describe()["tools"]);
+
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "initialize",
+            "params": initialize_params(serde_json::json!({}))
+        });
+        first.handle_value(initialize).unwrap();
+        let initialized = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/initialized"
+        });
+        first.handle_value(initialized).unwrap();
+        assert!(first.is_initialized());
+        assert!(!second.is_initialized());
+
+        let list = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+        let response = first.handle_value(list.clone()).unwrap().unwrap();
+        assert_eq!(response["result"]["tools"][0]["name"], "echo");
+        let response = second.handle_value(list).unwrap().unwrap();
+        assert_eq!(response["error"]["code"], -32003);
+    }
+
+    #[test]
+    fn test_request_elicitation() {
+        use std::sync::mpsc;
+
+        let server = Server::new("test-server", "1.0.0");
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": { "city": { "type": "string" } },
+            "required": ["city"]
+        });
+        let (tx, rx) = mpsc::channel();
+        let request = server.request_elicitation("Which city?", schema.clone(), move |result| {
+            tx.send(result.unwrap()).unwrap();
+        });
+
+        let request_value = serde_json::to_value(&request).unwrap();
+        assert_eq!(request_value["method"], "elicitation/create");
+        assert_eq!(request_value["params"]["message"], "Which city?");
+        assert_eq!(request_value["params"]["requestedSchema"], schema);
+
+        let response = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": request_value["id"],
+            "result": { "action": "accept", "content": { "city": "Lisbon" } }
+        });
+        assert!(server.handle_value(response).unwrap().is_none());
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            ElicitationResult::Accept {
+                content: serde_json::json!({ "city": "Lisbon" })
+            }
+        );
+
+        let declined: ElicitationResult =
+            serde_json::from_value(serde_json::json!({ "action": "decline" })).unwrap();
+        assert_eq!(declined, ElicitationResult::Decline);
+    }
+
+    #[test]
+    fn test_request_sampling() {
+        use std::sync::mpsc;
+
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let (tx, rx) = mpsc::channel();
+        let params = serde_json::json!({
+            "messages": [{ "role": "user", "content": { "type": "text", "text": "Hi" } }],
+            "maxTokens": 100
+        });
+        let request = server.request_sampling(params, move |result| {
+            tx.send(result.unwrap()).unwrap();
+        });
+        assert_eq!(request.method(), "sampling/createMessage");
+
+        let response = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": request.id(),
+            "result": {
+                "role": "assistant",
+                "content": { "type": "text", "text": "Hello!" },
+                "model": "example-model-1",
+                "stopReason": "endTurn"
+            }
+        });
+        server.handle_value(response).unwrap();
+        let result = rx.try_recv().unwrap();
+        assert_eq!(result.role, Role::Assistant);
+        assert!(matches!(result.content, Content::Text { ref text } if text == "Hello!"));
+        assert_eq!(result.model, "example-model-1");
+        assert_eq!(result.stop_reason.as_deref(), Some("endTurn"));
+    }
+
+    #[test]
+    fn test_request_ids_unique_across_sessions() {
+        let core = Arc::new(ServerCore::new("test-server", "1.0.0"));
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let session = core.new_session();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        session.send_request("ping", None, |_| {});
+                    }
+                    session.drain_outgoing_requests()
+                })
+            })
+            .collect();
+
+        let mut ids = HashSet::new();
+        for thread in threads {
+            for request in thread.join().unwrap() {
+                assert!(ids.insert(request.id().clone()));
+            }
+        }
+        assert_eq!(ids.len(), 2000);
+    }
+
+    #[test]
+    fn test_cancel_outgoing() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let answered = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&answered);
+        let request = server.request_sampling(serde_json::json!({ "maxTokens": 10 }), move |_| {
+            flag.store(true, Ordering::SeqCst);
+        });
+
+        let cancelled = server.cancel_outgoing(request.id().clone());
+        assert_eq!(cancelled.method(), "notifications/cancelled");
+        assert_eq!(
+            cancelled.params().unwrap()["requestId"],
+            serde_json::to_value(request.id()).unwrap()
+        );
+
+        let response = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": request.id(),
+            "result": {
+                "role": "assistant",
+                "content": { "type": "text", "text": "Too late" },
+                "model": "example-model-1"
+            }
+        });
+        assert!(server.handle_value(response).unwrap().is_none());
+        assert!(!answered.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_resource_uri_normalization() {
+        let resource = |uri: &str| Resource {
+            uri: uri.to_string(),
+            name: "b".to_string(),
+            description: None,
+            mime_type: None,
+            size: None,
+            annotations: None,
+        };
+        let mut server = Server::new("test-server", "1.0.0");
+        for uri in ["", "notes.md", "/etc/hosts"] {
+            assert!(server
+                .add_resource(resource(uri), |_| Err("unused".into()))
+                .is_err());
+        }
+        server
+            .add_resource(resource("FILE:///a/../b"), |_| {
+                Ok(ResourceContents::Text {
+                    uri: "file:///b".to_string(),
+                    mime_type: None,
+                    text: "bee".to_string(),
+                })
+            })
+            .unwrap();
+        let server = initialized(server);
+
+        let read = |uri: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "resources/read",
+                "params": { "uri": uri }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+        assert_eq!(server.export_config().resources[0].uri, "file:///b");
+        for uri in ["file:///b", "file:///a/../b", "file:///./b"] {
+            assert_eq!(read(uri)["result"]["contents"][0]["text"], "bee");
+        }
+        assert_eq!(
+            normalize_uri("https://example.com/a/b/..").unwrap(),
+            "https://example.com/a/"
+        );
+        assert_eq!(
+            normalize_uri("urn:isbn:0451450523").unwrap(),
+            "urn:isbn:0451450523"
+        );
+    }
+
+    #[test]
+    fn test_resource_annotations_serde() {
+        let json = serde_json::json!({
+            "uri": "file:///notes.md",
+            "name": "notes",
+            "annotations": {
+                "audience": ["user", "assistant"],
+                "lastModified": "2025-01-31T12:00:00Z"
+            }
+        });
+        let resource: Resource = serde_json::from_value(json.clone()).unwrap();
+        let annotations = resource.annotations.as_ref().unwrap();
+        assert_eq!(
+            annotations.audience.as_deref(),
+            Some([Role::User, Role::Assistant].as_slice())
+        );
+        assert_eq!(annotations.priority, None);
+        assert_eq!(
+            annotations.last_modified.as_deref(),
+            Some("2025-01-31T12:00:00Z")
+        );
+
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server
+            .add_resource(resource, |_| Err("unused".into()))
+            .unwrap();
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "resources/list" });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(response["result"]["resources"][0], json);
+    }
+
+    #[test]
+    fn test_validate_outgoing() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        server.set_validate_outgoing(true);
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" });
+        assert!(server.handle_value(request).unwrap().is_some());
+
+        let id = serde_json::json!(1);
+        let valid = Response::success(RequestId::Number(1), Value::Null);
+        assert_eq!(response_violation(&valid, &id), None);
+        let both = Response {
+            error: Some(ErrorResponse::new(ErrorCode::InternalError, "boom")),
+            ..valid.clone()
+        };
+        assert!(response_violation(&both, &id)
+            .unwrap()
+            .contains("exactly one"));
+        let wrong_id = Response::success(RequestId::Number(2), Value::Null);
+        assert!(response_violation(&wrong_id, &id).unwrap().contains("id"));
+        let old_version = Response {
+            jsonrpc: "1.0".to_string(),
+            ..valid
+        };
+        assert!(response_violation(&old_version, &id).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid outgoing response")]
+    fn test_validate_outgoing_panics_in_debug() {
+        let server = Server::new("test-server", "1.0.0");
+        server.set_validate_outgoing(true);
+        let malformed = Response {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id: RequestId::Number(1),
+            result: None,
+            error: None,
+        };
+        server.check_outgoing(&malformed, &serde_json::json!(1));
+    }
+
+    #[test]
+    fn test_traceparent_propagation() {
+        const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut server = Server::new("test-server", "1.0.0");
+        server.register_tool(described_tool("summarize"), |_, ctx| {
+            let params = serde_json::json!({ "messages": [], "maxTokens": 10 });
+            ctx.send_request("sampling/createMessage", Some(params), |_| {});
+            Ok(vec![Content::Text {
+                text: ctx.traceparent().unwrap_or("none").to_string(),
+            }])
+        });
+        let server = initialized(server);
+        let call = |traceparent: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "summarize", "_meta": { "traceparent": traceparent } }
+            });
+            server.handle_value(request).unwrap().unwrap()["result"]["content"][0]["text"].clone()
+        };
+
+        assert_eq!(call(TRACEPARENT), TRACEPARENT);
+        let outgoing = server.drain_outgoing_requests();
+        assert_eq!(outgoing[0].method(), "sampling/createMessage");
+        let params = outgoing[0].params().unwrap();
+        assert_eq!(params["_meta"]["traceparent"], TRACEPARENT);
+        assert_eq!(params["maxTokens"], 10);
+
+        assert_eq!(call("not-a-traceparent"), "none");
+        let outgoing = server.drain_outgoing_requests();
+        assert!(outgoing[0].params().unwrap().get("_meta").is_none());
+    }
+
+    #[test]
+    fn test_prompts_get_validates_required_arguments() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.add_prompt(
+            Prompt {
+                name: "translate".to_string(),
+                description: None,
+                arguments: vec![
+                    PromptArgument {
+                        name: "text".to_string(),
+                        description: None,
+                        required: true,
+                    },
+                    PromptArgument {
+                        name: "language".to_string(),
+                        description: None,
+                        required: true,
+                    },
+                    PromptArgument {
+                        name: "tone".to_string(),
+                        description: None,
+                        required: false,
+                    },
+                ],
+            },
+            |arguments, _| {
+                Ok(vec![PromptMessage {
+                    role: Role::User,
+                    content: Content::Text {
+                        text: format!(
+                            "Translate {} to {}",
+                            arguments["text"], arguments["language"]
+                        ),
+                    },
+                }])
+            },
+        );
+
+        let get = |arguments: Value| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "prompts/get",
+                "params": { "name": "translate", "arguments": arguments }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+
+        let response = get(serde_json::json!({ "text": "hola", "language": "en" }));
+        assert_eq!(
+            response["result"]["messages"][0]["content"]["text"],
+            "Translate hola to en"
+        );
+
+        let response = get(serde_json::json!({ "text": "hola" }));
+        assert_eq!(response["error"]["code"], -32602);
+        assert_eq!(
+            response["error"]["message"],
+            "Missing required arguments: language"
+        );
+
+        let response = get(serde_json::json!({
+            "text": "hola",
+            "language": "en",
+            "unknown": "ignored"
+        }));
+        assert!(response["error"].is_null());
+    }
+
+    #[test]
+    fn test_prompt_result() {
+        let mut server = Server::new("test-server", "1.0.0");
+        let prompt = |name: &str| Prompt {
+            name: name.to_string(),
+            description: Some("A greeting".to_string()),
+            arguments: vec![],
+        };
+        server.add_prompt(prompt("greet"), |_, _| {
+            Ok(vec![PromptMessage {
+                role: Role::User,
+                content: Content::Text {
+                    text: "Hello".to_string(),
+                },
+            }])
+        });
+        server.add_prompt(prompt("farewell"), |_, _| {
+            Ok(PromptResult {
+                description: Some("A farewell".to_string()),
+                messages: vec![],
+            })
+        });
+        let server = initialized(server);
+        let get = |name: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "prompts/get",
+                "params": { "name": name }
+            });
+            let response = server.handle_value(request).unwrap().unwrap();
+            serde_json::from_value::<PromptResult>(response["result"].clone()).unwrap()
+        };
+
+        // Bare messages get the prompt's description
+        let result = get("greet");
+        assert_eq!(result.description.as_deref(), Some("A greeting"));
+        assert_eq!(result.messages.len(), 1);
+        assert_eq!(get("farewell").description.as_deref(), Some("A farewell"));
+    }
+
+    #[test]
+    fn test_resource_link_content_round_trip() {
+        let link = Content::ResourceLink {
+            uri: "file:///reports/q3.csv".to_string(),
+            name: "q3.csv".to_string(),
+            description: Some("Quarterly report".to_string()),
+            mime_type: Some("text/csv".to_string()),
+        };
+        let value = serde_json::to_value(&link).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "type": "resource_link",
+                "uri": "file:///reports/q3.csv",
+                "name": "q3.csv",
+                "description": "Quarterly report",
+                "mimeType": "text/csv"
+            })
+        );
+        let parsed: Content = serde_json::from_value(value).unwrap();
+        assert!(
+            matches!(parsed, Content::ResourceLink { ref uri, .. } if uri == "file:///reports/q3.csv")
+        );
+    }
+
+    #[test]
+    fn test_tool_returns_resource_link() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(
+            Tool {
+                name: "export".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |_, _| {
+                Ok(vec![Content::ResourceLink {
+                    uri: "file:///exports/data.json".to_string(),
+                    name: "data.json".to_string(),
+                    description: None,
+                    mime_type: None,
+                }])
+            },
+        );
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "export" }
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(
+            response["result"]["content"],
+            serde_json::json!([{
+                "type": "resource_link",
+                "uri": "file:///exports/data.json",
+                "name": "data.json"
+            }])
+        );
+    }
+
+    #[test]
+    fn test_parse_error_reports_position() {
+        let server = Server::new("test-server", "1.0.0");
+        let malformed = "{\"jsonrpc\": \"2.0\",\n  \"id\": 1,\n  \"method\": }";
+
+        let response: Value =
+            serde_json::from_str(&server.handle_message(malformed).unwrap().unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], -32700);
+        assert!(response["id"].is_null());
+        assert!(response["error"].get("data").is_none());
+
+        server.set_verbose_errors(true);
+        let response: Value =
+            serde_json::from_str(&server.handle_message(malformed).unwrap().unwrap()).unwrap();
+        assert_eq!(
+            response["error"]["data"],
+            serde_json::json!({ "line": 3, "column": 13 })
+        );
+    }
+
+    #[test]
+    fn test_stats_method() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.enable_stats();
+        assert!(server.supported_methods().contains(&"x-mcp/stats"));
+
+        for id in 1..=3 {
+            let ping = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "ping" });
+            server.handle_value(ping).unwrap();
+        }
+        let unknown = serde_json::json!({ "jsonrpc": "2.0", "id": 4, "method": "nope" });
+        server.handle_value(unknown).unwrap();
+
+        let stats = serde_json::json!({ "jsonrpc": "2.0", "id": 5, "method": "x-mcp/stats" });
+        let response = server.handle_value(stats).unwrap().unwrap();
+        let result = &response["result"];
+        assert_eq!(result["methods"]["ping"], 3);
+        assert_eq!(result["methods"]["unknown"], 1);
+        assert!(result["methods"].get("nope").is_none());
+        // The handshake's `initialize` is counted too
+        assert_eq!(result["totalRequests"], 5);
+        assert_eq!(result["errorCount"], 1);
+        assert!(result["uptimeSeconds"].is_number());
+    }
+
+    #[test]
+    fn test_chunked_resource_read() {
+        let original: Vec<u8> = (0..=255u8).cycle().take(2500).collect();
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        let blob = original.clone();
+        server
+            .add_chunked_resource(
+                Resource {
+                    uri: "file:///big.bin".to_string(),
+                    name: "big".to_string(),
+                    description: None,
+                    mime_type: Some("application/octet-stream".to_string()),
+                    size: None,
+                    annotations: None,
+                },
+                1000,
+                move |offset, len, _| {
+                    let start = usize::try_from(offset).unwrap().min(blob.len());
+                    let end = (start + len).min(blob.len());
+                    Ok(ResourceChunk {
+                        data: blob[start..end].to_vec(),
+                        total_size: blob.len() as u64,
+                    })
+                },
+            )
+            .unwrap();
+
+        let read = |meta: Value| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "resources/read",
+                "params": { "uri": "file:///big.bin", "_meta": meta }
+            });
+            server.handle_value(request).unwrap().unwrap()["result"].clone()
+        };
+
+        let mut reassembled = Vec::new();
+        let mut cursor = Some("0".to_string());
+        let mut pages = 0;
+        while let Some(chunk) = cursor {
+            let result = read(serde_json::json!({ "chunk": chunk }));
+            assert_eq!(result["_meta"]["totalSize"], 2500);
+            assert_eq!(result["_meta"]["chunkOffset"], pages * 1000);
+            let blob = result["contents"][0]["blob"].as_str().unwrap();
+            reassembled.extend(BASE64.decode(blob).unwrap());
+            cursor = result["_meta"]["nextChunk"].as_str().map(String::from);
+            pages += 1;
+        }
+        assert_eq!(pages, 3);
+        assert_eq!(reassembled, original);
+
+        // Without a cursor the whole resource comes back in one response
+        let result = read(serde_json::json!({}));
+        let blob = result["contents"][0]["blob"].as_str().unwrap();
+        assert_eq!(BASE64.decode(blob).unwrap(), original);
+        assert!(result.get("_meta").is_none());
+    }
+
+    #[test]
+    fn test_initialize_instructions() {
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": initialize_params(serde_json::json!({}))
+        });
+
+        let mut server = Server::new("test-server", "1.0.0");
+        let response = server.handle_value(initialize.clone()).unwrap().unwrap();
+        assert!(response["result"].get("instructions").is_none());
+
+        server.set_instructions("Call `search` before `fetch`.");
+        server.reset();
+        let response = server.handle_value(initialize).unwrap().unwrap();
+        assert_eq!(
+            response["result"]["instructions"],
+            "Call `search` before `fetch`."
+        );
+    }
+
+    #[test]
+    fn test_denied_method() {
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(
+            Tool {
+                name: "echo".to_string(),
+                description: None,
+                input_schema: serde_json::json!({ "type": "object" }),
+                annotations: None,
+                meta: None,
+            },
+            |_, _| Ok(vec![]),
+        );
+        server.deny_method("tools/call");
+
+        let call = |method: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": method,
+                "params": { "name": "echo", "arguments": {} }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+        assert_eq!(call("tools/call")["error"]["code"], -32601);
+        assert_eq!(call("tools/list")["result"]["tools"][0]["name"], "echo");
+        assert!(!server.supported_methods().contains(&"tools/call"));
+    }
+
+    #[test]
+    fn test_allow_only_ping() {
+        let mut server = Server::new("test-server", "1.0.0");
+        server.allow_only(&["ping"]);
+
+        let call = |method: &str| {
+            let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method });
+            server.handle_value(request).unwrap().unwrap()
+        };
+        assert!(call("ping")["result"].is_object());
+        assert_eq!(call("resources/list")["error"]["code"], -32601);
+        assert_eq!(call("tools/list")["error"]["code"], -32601);
+        assert_eq!(server.supported_methods(), vec!["ping"]);
+    }
+
+    #[test]
+    fn test_tool_annotations() {
+        let tool = Tool {
+            name: "lookup".to_string(),
+            description: None,
+            input_schema: serde_json::json!({ "type": "object" }),
+            annotations: Some(ToolAnnotations {
+                read_only_hint: Some(true),
+                idempotent_hint: Some(true),
+                ..Default::default()
+            }),
+            meta: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&tool).unwrap()["annotations"],
+            serde_json::json!({ "readOnlyHint": true, "idempotentHint": true })
+        );
+
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(tool, |_, _| Ok(vec![]));
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(
+            response["result"]["tools"][0]["annotations"]["readOnlyHint"],
+            true
+        );
+    }
+
+    #[test]
+    fn test_tool_usage_hint() {
+        let tool = |name: &str| Tool {
+            name: name.to_string(),
+            description: None,
+            input_schema: serde_json::json!({ "type": "object" }),
+            annotations: None,
+            meta: None,
+        };
+        let mut server = Server::new("test-server", "1.0.0");
+        server.register_tool(
+            tool("search").with_usage("Prefer exact phrases in quotes."),
+            |_, _| Ok(vec![]),
+        );
+        server.register_tool(tool("echo"), |_, _| Ok(vec![]));
+        let server = initialized(server);
+
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+        let response = server.handle_value(request).unwrap().unwrap();
+        let tools = &response["result"]["tools"];
+        assert_eq!(
+            tools[0]["_meta"],
+            serde_json::json!({ "usage": "Prefer exact phrases in quotes." })
+        );
+        assert!(tools[1].get("_meta").is_none());
+
+        let parsed: Tool = serde_json::from_value(tools[0].clone()).unwrap();
+        assert_eq!(parsed.usage(), Some("Prefer exact phrases in quotes."));
+        let parsed: Tool = serde_json::from_value(tools[1].clone()).unwrap();
+        assert_eq!(parsed.usage(), None);
+    }
+
+    #[test]
+    fn test_tool_meta_round_trips() {
+        let meta = serde_json::json!({ "usage": "Be brief.", "vendor/rank": 3 });
+        let value = serde_json::json!({
+            "name": "search",
+            "inputSchema": { "type": "object" },
+            "_meta": meta,
+        });
+        let tool: Tool = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(tool.usage(), Some("Be brief."));
+        assert_eq!(serde_json::to_value(&tool).unwrap(), value);
+
+        let tool = tool.with_usage("Be thorough.");
+        let meta = &serde_json::to_value(&tool).unwrap()["_meta"];
+        assert_eq!(meta["usage"], "Be thorough.");
+        assert_eq!(meta["vendor/rank"], 3);
+    }
+
+    #[test]
+    fn test_pretty_output() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#;
+
+        let compact = server.handle_message(request).unwrap().unwrap();
+        assert!(!compact.contains('\n'));
+
+        server.set_pretty_output(true);
+        let pretty = server.handle_message(request).unwrap().unwrap();
+        assert!(pretty.contains('\n'));
+
+        assert_eq!(
+            serde_json::from_str::<Value>(&compact).unwrap(),
+            serde_json::from_str::<Value>(&pretty).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_reinit_policy() {
+        let initialize = |id: i64, client: &str| {
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": { "name": client, "version": "1.0.0" }
+                }
+            })
+        };
+
+        let server = Server::new("test-server", "1.0.0");
+        let first = server.handle_value(initialize(1, "a")).unwrap().unwrap();
+        assert!(first["result"].is_object());
+        let second = server.handle_value(initialize(2, "a")).unwrap().unwrap();
+        assert_eq!(second["error"]["code"], -32600);
+        assert_eq!(second["error"]["message"], "Already initialized");
+
+        let mut server = Server::new("test-server", "1.0.0");
+        server.set_reinit_policy(ReinitPolicy::Idempotent);
+        let first = server.handle_value(initialize(1, "a")).unwrap().unwrap();
+        let second = server.handle_value(initialize(2, "a")).unwrap().unwrap();
+        assert_eq!(second["result"], first["result"]);
+        let differing = server.handle_value(initialize(3, "b")).unwrap().unwrap();
+        assert_eq!(differing["error"]["code"], -32600);
+
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.set_reinit_policy(ReinitPolicy::AllowReset);
+        server.handle_value(initialize(1, "a")).unwrap();
+        let second = server.handle_value(initialize(2, "b")).unwrap().unwrap();
+        assert!(second["result"].is_object());
+        // The session starts over, handshake included
+        assert!(!server.is_initialized());
+    }
+
+    #[test]
+    fn test_structured_content_preference() {
+        let mut server = Server::new("test-server", "1.0.0");
+        server.register_structured_tool(
+            described_tool("weather"),
+            |_, _| {
+                Ok(vec![Content::Text {
+                    text: "Sunny, 21°C".to_string(),
+                }])
+            },
+            |_, _| Ok(serde_json::json!({ "sky": "sunny", "celsius": 21 })),
+        );
+        let call = |server: &Server, meta: Value| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "weather", "_meta": meta }
+            });
+            server.handle_value(request).unwrap().unwrap()["result"].clone()
+        };
+
+        let server = initialized(server);
+        let result = call(&server, serde_json::json!({}));
+        assert_eq!(result["content"][0]["text"], "Sunny, 21°C");
+        assert!(result.get("structuredContent").is_none());
+
+        let result = call(
+            &server,
+            serde_json::json!({ "preferredContent": "structured" }),
+        );
+        assert_eq!(result["structuredContent"]["celsius"], 21);
+        assert_eq!(
+            serde_json::from_str::<Value>(result["content"][0]["text"].as_str().unwrap()).unwrap(),
+            result["structuredContent"]
+        );
+
+        // Clients can also state the preference once, in `initialize`
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "initialize",
+            "params": initialize_params(serde_json::json!({ "content": { "structured": {} } }))
+        });
+        server.reset();
+        server.handle_value(initialize).unwrap();
+        let server = initialized(server);
+        assert!(call(&server, serde_json::json!({}))["structuredContent"].is_object());
+        let result = call(&server, serde_json::json!({ "preferredContent": "text" }));
+        assert!(result.get("structuredContent").is_none());
+    }
+
+    #[test]
+    fn test_resource_updated_vs_list_changed() {
+        let mut capabilities = ServerCore::new("test-server", "1.0.0").capabilities;
+        capabilities.resources = Some(ResourcesCapability {
+            subscribe: true,
+            list_changed: true,
+        });
+        let mut server = initialized(Server::with_capabilities(
+            "test-server",
+            "1.0.0",
+            capabilities,
+        ));
+
+        server.notify_resource_updated("file:///unsubscribed.txt");
+        assert!(server.drain_notifications().is_empty());
+
+        server
+            .add_resource(
+                Resource {
+                    uri: "file:///new.txt".to_string(),
+                    name: "new".to_string(),
+                    description: None,
+                    mime_type: None,
+                    size: None,
+                    annotations: None,
+                },
+                |_| Err("unused".into()),
+            )
+            .unwrap();
+        let notifications = server.drain_notifications();
+        assert_eq!(
+            notifications,
+            [Notification::resource_list_changed()],
+            "list_changed doesn't depend on subscriptions"
+        );
+    }
+
+    #[test]
+    fn test_reset_session() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        let subscribe = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "resources/subscribe",
+            "params": { "uri": "file:///a" }
+        });
+        server.handle_value(subscribe).unwrap().unwrap();
+
+        server.reset();
+        assert!(!server.is_initialized());
+        server.notify_resource_updated("file:///a");
+        assert!(server.drain_notifications().is_empty());
+
+        let init_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "initialize",
+            "params": initialize_params(serde_json::json!({}))
+        });
+        let response = server.handle_value(init_request).unwrap().unwrap();
+        assert!(response["result"]["serverInfo"].is_object());
+        let server = initialized(server);
+        assert!(server.is_initialized());
+    }
+
+    #[test]
+    fn test_subscription_limit() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        server.set_max_subscriptions(2);
+
+        let call = |method: &str, uri: &str| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": method,
+                "params": { "uri": uri }
+            });
+            server.handle_value(request).unwrap().unwrap()
+        };
+        assert!(call("resources/subscribe", "file:///a")
+            .get("error")
+            .is_none());
+        assert!(call("resources/subscribe", "file:///b")
+            .get("error")
+            .is_none());
+        // Repeating an existing subscription doesn't take another slot
+        assert!(call("resources/subscribe", "file:///a")
+            .get("error")
+            .is_none());
+        assert_eq!(
+            call("resources/subscribe", "file:///c")["error"]["code"],
+            -32000
+        );
+
+        call("resources/unsubscribe", "file:///a");
+        assert!(call("resources/subscribe", "file:///c")
+            .get("error")
+            .is_none());
+    }
+
+    #[test]
+    fn test_initialize_requires_params() {
+        let server = Server::new("test-server", "1.0.0");
+        for field in ["protocolVersion", "capabilities", "clientInfo"] {
+            let mut params = initialize_params(serde_json::json!({}));
+            params.as_object_mut().unwrap().remove(field);
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": params
+            });
+            let response = server.handle_value(request).unwrap().unwrap();
+            assert_eq!(response["error"]["code"], -32602, "{field}");
+            let message = response["error"]["message"].as_str().unwrap();
+            assert!(message.contains(field), "{message}");
+        }
+        // Rejected requests negotiate nothing
+        assert_eq!(server.client_capabilities(), None);
+
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "initialize" });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(response["error"]["code"], -32602);
+    }
+
+    #[test]
+    fn test_initialize_result_shape() {
+        let mut server = Server::new("test-server", "1.0.0");
+        server.set_instructions("Be brief.");
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": initialize_params(serde_json::json!({}))
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+
+        let result: InitializeResult = serde_json::from_value(response["result"].clone()).unwrap();
+        assert_eq!(result.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(result.server_info.name, "test-server");
+        assert_eq!(result.capabilities, server.capabilities);
+        assert_eq!(result.instructions.as_deref(), Some("Be brief."));
+    }
+
+    crate::tool! {
+        /// Adds two numbers.
+        fn add(a: i64, b: i64) -> i64 {
+            a + b
+        }
+    }
+
+    crate::tool! {
+        /// Lists the MIME types of the images given.
+        fn image_types(images: Vec<Content>) -> Vec<String> {
+            images
+                .into_iter()
+                .filter_map(|content| match content {
+                    Content::Image { mime_type, .. } => Some(mime_type),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_tool_content_argument() {
+        let tool = image_types::tool();
+        let images = &tool.input_schema["properties"]["images"];
+        assert_eq!(images["type"], "array");
+        assert_eq!(
+            images["items"]["oneOf"][1]["properties"]["type"]["const"],
+            "image"
+        );
+
+        let mut server = Server::new("test-server", "1.0.0");
+        server.register_tool(tool, image_types::handler);
+        let server = initialized(server);
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": {
+                "name": "image_types",
+                "arguments": {
+                    "images": [
+                        { "type": "image", "data": "iVBORw==", "mimeType": "image/png" },
+                        { "type": "text", "text": "not an image" }
+                    ]
+                }
+            }
+        });
+        let response = server.handle_value(request).unwrap().unwrap();
+        assert_eq!(response["result"]["content"][0]["text"], r#"["image/png"]"#);
+    }
+
+    #[test]
+    fn test_tool_macro() {
+        let tool = add::tool();
+        assert_eq!(tool.description.as_deref(), Some("Adds two numbers."));
+        assert_eq!(
+            tool.input_schema,
+            serde_json::json!({
+                "type": "object",
+                "properties": { "a": { "type": "integer" }, "b": { "type": "integer" } },
+                "required": ["a", "b"]
+            })
+        );
+
+        let mut server = initialized(Server::new("test-server", "1.0.0"));
+        server.register_tool(tool, add::handler);
+        let call = |arguments: Value| {
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "add", "arguments": arguments }
+            });
+            server.handle_value(request).unwrap().unwrap()["result"].clone()
+        };
+        let result = call(serde_json::json!({ "a": 2, "b": 3 }));
+        assert_eq!(result["content"][0]["text"], "5");
+
+        let result = call(serde_json::json!({ "a": 2 }));
+        assert_eq!(result["isError"], true);
+    }
+
+    mod round_trip {
+        use super::*;
+        use proptest::prelude::*;
+
+        // Floats are left out: serde_json's default float parsing isn't
+        // guaranteed to be bit-exact, which is unrelated to these types
+        fn arb_value() -> impl Strategy<Value = Value> {
+            let leaf = prop_oneof![
+                Just(Value::Null),
+                any::<bool>().prop_map(Value::from),
+                any::<i64>().prop_map(Value::from),
+                ".*".prop_map(Value::from),
+            ];
+            leaf.prop_recursive(3, 16, 4, |inner| {
+                prop_oneof![
+                    prop::collection::vec(inner.clone(), 0..4).prop_map(Value::from),
+                    prop::collection::hash_map(".*", inner, 0..4)
+                        .prop_map(|map| Value::Object(map.into_iter().collect())),
+                ]
+            })
+        }
+
+        fn arb_id() -> impl Strategy<Value = RequestId> {
+            prop_oneof![
+                ".*".prop_map(RequestId::String),
+                any::<i64>().prop_map(RequestId::Number),
+                Just(RequestId::Null),
+            ]
+        }
+
+        fn arb_error() -> impl Strategy<Value = ErrorResponse> {
+            (any::<i32>(), ".*", proptest::option::of(arb_value())).prop_map(
+                |(code, message, data)| ErrorResponse {
+                    code,
+                    message,
+                    data,
+                },
+            )
+        }
+
+        fn round_trip<T>(value: &T) -> T
+        where
+            T: Serialize + serde::de::DeserializeOwned,
+        {
+            serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+        }
+
+        proptest! {
+            #[test]
+            fn request(id in arb_id(), method in ".*", params in proptest::option::of(arb_value())) {
+                let request = Request {
+                    jsonrpc: JSONRPC_VERSION.to_string(),
+                    id,
+                    method,
+                    params,
+                };
+                prop_assert_eq!(round_trip(&request), request);
+            }
+
+            #[test]
+            fn response(
+                id in arb_id(),
+                result in proptest::option::of(arb_value()),
+                error in proptest::option::of(arb_error()),
+            ) {
+                let response = Response {
+                    jsonrpc: JSONRPC_VERSION.to_string(),
+                    id,
+                    result,
+                    error,
+                };
+                prop_assert_eq!(round_trip(&response), response);
+            }
+
+            #[test]
+            fn notification(method in ".*", params in proptest::option::of(arb_value())) {
+                let notification = Notification::new(&method, params);
+                prop_assert_eq!(round_trip(&notification), notification);
+            }
+
+            #[test]
+            fn error_response(error in arb_error()) {
+                prop_assert_eq!(round_trip(&error), error);
+            }
+
+            #[test]
+            fn capabilities(
+                logging in proptest::option::of(arb_value()),
+                prompts in proptest::option::of(any::<bool>()),
+                resources in proptest::option::of((any::<bool>(), any::<bool>())),
+                tools in proptest::option::of(any::<bool>()),
+            ) {
+                let capabilities = ServerCapabilities {
+                    logging,
+                    prompts: prompts.map(|list_changed| PromptsCapability { list_changed }),
+                    resources: resources.map(|(subscribe, list_changed)| ResourcesCapability {
+                        subscribe,
+                        list_changed,
+                    }),
+                    tools: tools.map(|list_changed| ToolsCapability { list_changed }),
+                };
+                prop_assert_eq!(round_trip(&capabilities), capabilities);
+            }
+        }
+
+        #[test]
+        fn null_result_is_not_absent() {
+            let response: Response =
+                serde_json::from_str(r#"{"jsonrpc":"2.0","id":1,"result":null}"#).unwrap();
+            assert_eq!(response.result, Some(Value::Null));
+            assert!(response.error.is_none());
+        }
     }
 }
@@ -0,0 +1,181 @@
+use crate::Content;
+use serde_json::Value;
+
+/// A type usable as a `tool!` argument: it knows its JSON Schema and whether
+/// callers have to supply it.
+pub trait ToolArgument {
+    fn schema() -> Value;
+
+    /// `Option` arguments may be left out.
+    const REQUIRED: bool = true;
+}
+
+macro_rules! schema_type {
+    ($schema:literal: $($ty:ty),*) => {
+        $(impl ToolArgument for $ty {
+            fn schema() -> Value {
+                serde_json::json!({ "type": $schema })
+            }
+        })*
+    };
+}
+
+schema_type!("integer": i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+schema_type!("number": f32, f64);
+schema_type!("boolean": bool);
+schema_type!("string": String);
+schema_type!("object": Value);
+
+/// A content block as in tool results, for multimodal tools taking text or
+/// images as input; `Vec<Content>` accepts several.
+impl ToolArgument for Content {
+    fn schema() -> Value {
+        let string = serde_json::json!({ "type": "string" });
+        serde_json::json!({
+            "type": "object",
+            "oneOf": [
+                {
+                    "properties": { "type": { "const": "text" }, "text": string },
+                    "required": ["type", "text"]
+                },
+                {
+                    "properties": {
+                        "type": { "const": "image" },
+                        "data": string,
+                        "mimeType": string
+                    },
+                    "required": ["type", "data", "mimeType"]
+                },
+                {
+                    "properties": {
+                        "type": { "const": "resource" },
+                        "resource": { "type": "object" }
+                    },
+                    "required": ["type", "resource"]
+                },
+                {
+                    "properties": {
+                        "type": { "const": "resource_link" },
+                        "uri": string,
+                        "name": string
+                    },
+                    "required": ["type", "uri", "name"]
+                }
+            ]
+        })
+    }
+}
+
+impl<T: ToolArgument> ToolArgument for Vec<T> {
+    fn schema() -> Value {
+        serde_json::json!({ "type": "array", "items": T::schema() })
+    }
+}
+
+impl<T: ToolArgument> ToolArgument for Option<T> {
+    fn schema() -> Value {
+        T::schema()
+    }
+
+    const REQUIRED: bool = false;
+}
+
+/// Declares a tool from a function. The doc comment becomes the description
+/// and the typed parameters the input schema. The expansion is a module named
+/// after the function with `tool()` returning the descriptor and `handler`,
+/// which deserializes the arguments, calls the function, and returns its
+/// result as text content (strings as-is, anything else as JSON):
+///
+/// ```ignore
+/// tool! {
+///     /// Adds two numbers.
+///     fn add(a: i64, b: i64) -> i64 {
+///         a + b
+///     }
+/// }
+/// server.register_tool(add::tool(), add::handler);
+/// ```
+#[macro_export]
+macro_rules! tool {
+    (
+        $(#[doc = $doc:literal])*
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block
+    ) => {
+        $vis mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            fn run($($arg: $ty),*) -> $ret $body
+
+            pub fn tool() -> $crate::Tool {
+                $crate::Tool {
+                    name: stringify!($name).to_string(),
+                    description: $crate::__tool_support::description(&[$($doc),*]),
+                    input_schema: $crate::__tool_support::input_schema(&[$((
+                        stringify!($arg),
+                        <$ty as $crate::ToolArgument>::schema(),
+                        <$ty as $crate::ToolArgument>::REQUIRED,
+                    )),*]),
+                    annotations: None,
+                    meta: None,
+                }
+            }
+
+            pub fn handler(
+                arguments: $crate::__tool_support::Value,
+                _ctx: &$crate::RequestContext,
+            ) -> $crate::HandlerResult<Vec<$crate::Content>> {
+                $(let $arg: $ty = $crate::__tool_support::argument(&arguments, stringify!($arg))?;)*
+                $crate::__tool_support::output(run($($arg),*))
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+pub mod support {
+    use crate::{Content, HandlerResult};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    pub use serde_json::Value;
+
+    pub fn description(lines: &[&str]) -> Option<String> {
+        let text = lines
+            .iter()
+            .map(|line| line.trim())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let text = text.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    pub fn input_schema(arguments: &[(&str, Value, bool)]) -> Value {
+        let properties: serde_json::Map<String, Value> = arguments
+            .iter()
+            .map(|(name, schema, _)| (name.to_string(), schema.clone()))
+            .collect();
+        let required: Vec<&str> = arguments
+            .iter()
+            .filter(|(_, _, required)| *required)
+            .map(|(name, _, _)| *name)
+            .collect();
+        serde_json::json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        })
+    }
+
+    pub fn argument<T: DeserializeOwned>(arguments: &Value, name: &str) -> HandlerResult<T> {
+        let value = arguments.get(name).cloned().unwrap_or(Value::Null);
+        serde_json::from_value(value).map_err(|err| format!("argument `{name}`: {err}").into())
+    }
+
+    pub fn output<T: Serialize>(output: T) -> HandlerResult<Vec<Content>> {
+        let text = match serde_json::to_value(output)? {
+            Value::String(text) => text,
+            value => value.to_string(),
+        };
+        Ok(vec![Content::Text { text }])
+    }
+}
@@ -0,0 +1,148 @@
+use crate::Session;
+use std::fmt::Write;
+use std::time::Duration;
+
+// Upper bounds of the latency buckets in seconds, as in the Prometheus clients
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Request latencies bucketed by `LATENCY_BUCKETS`, each count cumulative.
+#[derive(Debug, Default)]
+pub(crate) struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub(crate) fn observe(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+// Escapes a label value per the exposition format
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Session {
+    /// The session's request counters in the Prometheus text exposition
+    /// format, for a scrape endpoint such as `/metrics`: requests per method,
+    /// error responses per JSON-RPC code, and a request latency histogram.
+    /// Methods the server doesn't know share the `method="unknown"` series, so
+    /// clients can't create series at will.
+    pub fn metrics_prometheus(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let stats = &state.stats;
+        // Writing to a `String` can't fail
+        let mut out = String::new();
+
+        out.push_str("# HELP mcp_requests_total Requests handled, by method.\n");
+        out.push_str("# TYPE mcp_requests_total counter\n");
+        let mut methods: Vec<_> = stats.per_method.iter().collect();
+        methods.sort();
+        for (method, count) in methods {
+            let _ = writeln!(
+                out,
+                "mcp_requests_total{{method=\"{}\"}} {count}",
+                label(method)
+            );
+        }
+
+        out.push_str("# HELP mcp_errors_total Error responses, by JSON-RPC error code.\n");
+        out.push_str("# TYPE mcp_errors_total counter\n");
+        let mut codes: Vec<_> = stats.errors_by_code.iter().collect();
+        codes.sort();
+        for (code, count) in codes {
+            let _ = writeln!(out, "mcp_errors_total{{code=\"{code}\"}} {count}");
+        }
+
+        let latency = &stats.latency;
+        out.push_str("# HELP mcp_request_duration_seconds Time spent handling requests.\n");
+        out.push_str("# TYPE mcp_request_duration_seconds histogram\n");
+        for (count, bound) in latency.buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "mcp_request_duration_seconds_bucket{{le=\"{bound}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "mcp_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            latency.count
+        );
+        let _ = writeln!(out, "mcp_request_duration_seconds_sum {}", latency.sum);
+        let _ = writeln!(out, "mcp_request_duration_seconds_count {}", latency.count);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::initialized;
+    use crate::{HttpTransport, Server};
+
+    #[test]
+    fn test_metrics_prometheus() {
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        for id in 1..=3 {
+            let ping = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "ping" });
+            server.handle_value(ping).unwrap();
+        }
+        let unknown = serde_json::json!({ "jsonrpc": "2.0", "id": 4, "method": "nope" });
+        server.handle_value(unknown).unwrap();
+
+        let metrics = server.metrics_prometheus();
+        let lines: Vec<&str> = metrics.lines().collect();
+        assert!(lines.contains(&"# TYPE mcp_requests_total counter"));
+        assert!(lines.contains(&"mcp_requests_total{method=\"ping\"} 3"));
+        assert!(lines.contains(&"mcp_requests_total{method=\"unknown\"} 1"));
+        assert!(lines.contains(&"mcp_errors_total{code=\"-32601\"} 1"));
+        // The handshake's `initialize` is counted too
+        assert!(lines.contains(&"mcp_request_duration_seconds_bucket{le=\"+Inf\"} 5"));
+        assert!(lines.contains(&"mcp_request_duration_seconds_count 5"));
+
+        let response = HttpTransport::new(server).metrics();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, metrics);
+    }
+
+    #[test]
+    fn test_metrics_method_labels_are_bounded() {
+        let mut server = Server::new("test-server", "1.0.0");
+        server.register_method("x-acme/status", |_, _| Ok(serde_json::json!({})));
+        let server = initialized(server);
+        for id in 1..=50 {
+            let method = format!("x-random/{id}");
+            let request = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": method });
+            server.handle_value(request).unwrap();
+        }
+        let status = serde_json::json!({ "jsonrpc": "2.0", "id": 51, "method": "x-acme/status" });
+        server.handle_value(status).unwrap();
+
+        let metrics = server.metrics_prometheus();
+        let series: Vec<&str> = metrics
+            .lines()
+            .filter(|line| line.starts_with("mcp_requests_total{"))
+            .collect();
+        assert_eq!(
+            series,
+            [
+                "mcp_requests_total{method=\"initialize\"} 1",
+                "mcp_requests_total{method=\"unknown\"} 50",
+                "mcp_requests_total{method=\"x-acme/status\"} 1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{McpError, Session};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+/// One line of a recording made by `Session::enable_recording`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "direction", content = "message", rename_all = "lowercase")]
+pub enum Recorded {
+    Inbound(Value),
+    /// Inbound text that wasn't valid JSON, kept as it arrived.
+    Unparsed(String),
+    Outbound(Value),
+}
+
+/// A recorded response that replaying its request didn't reproduce.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayMismatch {
+    /// The recording's line number, counting from 1, of the inbound message.
+    pub line: usize,
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+}
+
+impl Session {
+    /// Appends every message the session handles, and every response to it,
+    /// to the JSONL file at `path`, for feeding back into `replay` later. This
+    /// covers each `handle_*` method and the serve loops built on them.
+    pub fn enable_recording(&self, path: impl AsRef<Path>) -> Result<(), McpError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.state.lock().unwrap().recording = Some(file);
+        Ok(())
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        self.state.lock().unwrap().recording.is_some()
+    }
+
+    // Appends `inbound` and the response to it as adjacent lines, so replay
+    // pairs them up even when concurrent requests finish out of order
+    pub(crate) fn record(
+        &self,
+        inbound: Recorded,
+        outbound: Option<Value>,
+    ) -> Result<(), McpError> {
+        let mut lines = serde_json::to_vec(&inbound)?;
+        lines.push(b'\n');
+        if let Some(outbound) = outbound {
+            lines.extend(serde_json::to_vec(&Recorded::Outbound(outbound))?);
+            lines.push(b'\n');
+        }
+        if let Some(file) = self.state.lock().unwrap().recording.as_mut() {
+            file.write_all(&lines)?;
+        }
+        Ok(())
+    }
+}
+
+/// Feeds the inbound messages of the recording at `path` to `server` in order
+/// and compares its responses with the recorded ones. Returns every response
+/// that differs; an empty list means the session was reproduced exactly.
+pub fn replay(path: impl AsRef<Path>, server: &Session) -> Result<Vec<ReplayMismatch>, McpError> {
+    let mut entries = Vec::new();
+    for (index, line) in BufReader::new(std::fs::File::open(path)?)
+        .lines()
+        .enumerate()
+    {
+        let line = line?;
+        if !line.trim().is_empty() {
+            entries.push((index + 1, serde_json::from_str::<Recorded>(&line)?));
+        }
+    }
+
+    let mut mismatches = Vec::new();
+    let mut entries = entries.into_iter().peekable();
+    while let Some((line, entry)) = entries.next() {
+        // Each message takes the path it took live. Outbound entries are
+        // consumed alongside the message they answer.
+        let actual = match entry {
+            Recorded::Inbound(message) => server.handle_value(message)?,
+            Recorded::Unparsed(text) => match server.handle_message(&text)? {
+                Some(response) => Some(serde_json::from_str(&response)?),
+                None => None,
+            },
+            Recorded::Outbound(_) => continue,
+        };
+        let expected = match entries.peek() {
+            Some((_, Recorded::Outbound(_))) => match entries.next() {
+                Some((_, Recorded::Outbound(response))) => Some(response),
+                _ => None,
+            },
+            _ => None,
+        };
+        if actual != expected {
+            mismatches.push(ReplayMismatch {
+                line,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::initialize_params;
+    use crate::Server;
+
+    #[test]
+    fn test_record_and_replay() {
+        let path =
+            std::env::temp_dir().join(format!("mcp_rs-recording-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let server = Server::new("test-server", "1.0.0");
+        server.enable_recording(&path).unwrap();
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": initialize_params(serde_json::json!({}))
+        });
+        server.handle_message(&initialize.to_string()).unwrap();
+        server
+            .handle_message(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+            .unwrap();
+        server
+            .handle_value(serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }))
+            .unwrap();
+        server.handle_message("{oops").unwrap();
+
+        let recording = std::fs::read_to_string(&path).unwrap();
+        let entries: Vec<Recorded> = recording
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 7);
+        assert!(matches!(entries[1], Recorded::Outbound(_)));
+        assert_eq!(entries[5], Recorded::Unparsed("{oops".to_string()));
+
+        let fresh = Server::new("test-server", "1.0.0");
+        assert_eq!(replay(&path, &fresh).unwrap(), []);
+
+        // A server that answers differently is caught
+        let renamed = Server::new("other-server", "1.0.0");
+        let mismatches = replay(&path, &renamed).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].line, 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Reads back a recording, removing the file
+    fn take_recording(path: &Path) -> Vec<Recorded> {
+        let recording = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        recording
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_handle_message_to_writer_is_recorded() {
+        let path = std::env::temp_dir().join(format!(
+            "mcp_rs-recording-writer-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let server = Server::new("test-server", "1.0.0");
+        server.enable_recording(&path).unwrap();
+        let mut out = Vec::new();
+        server
+            .handle_message_to_writer(r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#, &mut out)
+            .unwrap();
+        server.handle_message_to_writer("{oops", &mut out).unwrap();
+
+        let entries = take_recording(&path);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(
+            entries[0],
+            Recorded::Inbound(serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" }))
+        );
+        assert!(matches!(&entries[1], Recorded::Outbound(response) if response["id"] == 1));
+        assert_eq!(entries[2], Recorded::Unparsed("{oops".to_string()));
+        assert!(
+            matches!(&entries[3], Recorded::Outbound(response) if response["error"]["code"] == -32700)
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_serve_async_is_recorded() {
+        let path = std::env::temp_dir().join(format!(
+            "mcp_rs-recording-async-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let server = Server::new("test-server", "1.0.0");
+        server.enable_recording(&path).unwrap();
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#,
+            "\n",
+            r#"[{"jsonrpc":"2.0","id":2,"method":"ping"},{"jsonrpc":"2.0","id":3,"method":"ping"}]"#,
+            "\n",
+            "{oops\n",
+        );
+        let mut output = Vec::new();
+        server
+            .serve_async(input.as_bytes(), &mut output)
+            .await
+            .unwrap();
+
+        // Each message is recorded once, batches whole, next to its response.
+        // The batch runs on its own task, so it may be recorded last.
+        let recording = std::fs::read_to_string(&path).unwrap();
+        let entries: Vec<Recorded> = recording
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 6);
+        for pair in entries.chunks(2) {
+            match (&pair[0], &pair[1]) {
+                (Recorded::Inbound(request), Recorded::Outbound(response)) => {
+                    assert_eq!(request.is_array(), response.is_array());
+                }
+                (Recorded::Unparsed(text), Recorded::Outbound(response)) => {
+                    assert_eq!(text, "{oops");
+                    assert_eq!(response["error"]["code"], -32700);
+                }
+                _ => panic!("unpaired entries: {pair:?}"),
+            }
+        }
+
+        let fresh = Server::new("test-server", "1.0.0");
+        assert_eq!(replay(&path, &fresh).unwrap(), []);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{McpError, Server, Transport};
+use std::io::{BufRead, Read, Write};
+
+/// The largest message body `Framing::read_message` accepts.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// How messages are delimited on a byte stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// One message per line, as in the MCP stdio transport.
+    #[default]
+    NewlineDelimited,
+    /// Each message is preceded by a `Content-Length: N` header and a blank
+    /// line, as in the Language Server Protocol.
+    ContentLength,
+}
+
+impl Framing {
+    /// Reads the next message body, or `None` once `input` is exhausted.
+    /// Bodies over `DEFAULT_MAX_MESSAGE_SIZE` bytes are rejected.
+    pub fn read_message<R: BufRead>(self, input: &mut R) -> Result<Option<String>, McpError> {
+        self.read_message_with_limit(input, DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    /// Like `read_message`, rejecting bodies over `max_size` bytes before
+    /// buffering them, so a peer can't make the reader allocate without bound.
+    pub fn read_message_with_limit<R: BufRead>(
+        self,
+        input: &mut R,
+        max_size: usize,
+    ) -> Result<Option<String>, McpError> {
+        match self {
+            Framing::NewlineDelimited => loop {
+                // Room for the line ending, so a line cut off at the limit is
+                // still seen to be too long
+                let mut line = String::new();
+                let limit = max_size as u64 + 2;
+                if input.by_ref().take(limit).read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                let message = line.trim_end_matches(['\r', '\n']);
+                if message.len() > max_size {
+                    return Err(framing_error("message exceeds the size limit"));
+                }
+                if !message.trim().is_empty() {
+                    return Ok(Some(message.to_string()));
+                }
+            },
+            Framing::ContentLength => {
+                let mut length = None;
+                let mut started = false;
+                loop {
+                    let mut line = String::new();
+                    if input.read_line(&mut line)? == 0 {
+                        if !started {
+                            return Ok(None);
+                        }
+                        return Err(framing_error("stream ended inside a header"));
+                    }
+                    let line = line.trim_end_matches(['\r', '\n']);
+                    if line.is_empty() {
+                        // Tolerate blank lines between messages
+                        if started {
+                            break;
+                        }
+                        continue;
+                    }
+                    started = true;
+                    // Other headers, such as `Content-Type`, are ignored
+                    let (name, value) = line
+                        .split_once(':')
+                        .ok_or_else(|| framing_error("malformed header"))?;
+                    if name.trim().eq_ignore_ascii_case("Content-Length") {
+                        let value = value.trim().parse::<usize>();
+                        length = Some(value.map_err(|_| framing_error("invalid Content-Length"))?);
+                    }
+                }
+
+                let length = length.ok_or_else(|| framing_error("missing Content-Length"))?;
+                if length > max_size {
+                    return Err(framing_error("message exceeds the size limit"));
+                }
+                let mut body = Vec::with_capacity(length);
+                input.by_ref().take(length as u64).read_to_end(&mut body)?;
+                if body.len() < length {
+                    return Err(McpError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+                }
+                String::from_utf8(body)
+                    .map(Some)
+                    .map_err(|_| framing_error("body is not UTF-8"))
+            }
+        }
+    }
+
+    /// Writes `message` in this framing. Newline-delimited messages must not
+    /// contain newlines themselves, so don't combine it with pretty output.
+    pub fn write_message<W: Write>(self, output: &mut W, message: &str) -> Result<(), McpError> {
+        match self {
+            Framing::NewlineDelimited => writeln!(output, "{message}")?,
+            Framing::ContentLength => {
+                write!(output, "Content-Length: {}\r\n\r\n{message}", message.len())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn framing_error(message: &str) -> McpError {
+    McpError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        message,
+    ))
+}
+
+/// A `Transport` over a byte stream pair such as stdin and stdout, with
+/// messages delimited by a `Framing`.
+pub struct FramedTransport<R, W> {
+    input: R,
+    output: W,
+    framing: Framing,
+    max_message_size: usize,
+}
+
+impl<R: BufRead, W: Write> FramedTransport<R, W> {
+    pub fn new(input: R, output: W, framing: Framing) -> Self {
+        FramedTransport {
+            input,
+            output,
+            framing,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Caps the size of an incoming message body; a larger one fails
+    /// `receive`.
+    pub fn with_max_message_size(mut self, max_size: usize) -> Self {
+        self.max_message_size = max_size;
+        self
+    }
+}
+
+impl<R: BufRead, W: Write> Transport for FramedTransport<R, W> {
+    fn receive(&mut self) -> Result<Option<String>, McpError> {
+        self.framing
+            .read_message_with_limit(&mut self.input, self.max_message_size)
+    }
+
+    fn send(&mut self, message: &str) -> Result<(), McpError> {
+        self.framing.write_message(&mut self.output, message)
+    }
+
+    fn flush(&mut self) -> Result<(), McpError> {
+        Ok(self.output.flush()?)
+    }
+}
+
+/// Serves a server over a pair of byte streams, usually stdin and stdout,
+/// in the chosen `Framing`.
+pub struct StdioTransport {
+    server: Server,
+    framing: Framing,
+    max_message_size: usize,
+}
+
+impl StdioTransport {
+    pub fn new(server: Server) -> Self {
+        StdioTransport {
+            server,
+            framing: Framing::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Caps the size of an incoming message body; a larger one ends `serve`
+    /// with an error.
+    pub fn with_max_message_size(mut self, max_size: usize) -> Self {
+        self.max_message_size = max_size;
+        self
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Like `Session::serve`, delimiting messages with the transport's framing.
+    pub fn serve<R: BufRead, W: Write>(&self, input: R, output: W) -> Result<(), McpError> {
+        let mut transport = FramedTransport::new(input, output, self.framing)
+            .with_max_message_size(self.max_message_size);
+        self.server.serve_transport(&mut transport)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::initialized;
+    use serde_json::Value;
+
+    #[test]
+    fn test_framing_round_trip() {
+        let message = r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#;
+        for framing in [Framing::NewlineDelimited, Framing::ContentLength] {
+            let mut buffer = Vec::new();
+            framing.write_message(&mut buffer, message).unwrap();
+            framing.write_message(&mut buffer, "{}").unwrap();
+
+            let mut input = buffer.as_slice();
+            assert_eq!(
+                framing.read_message(&mut input).unwrap().as_deref(),
+                Some(message)
+            );
+            assert_eq!(
+                framing.read_message(&mut input).unwrap().as_deref(),
+                Some("{}")
+            );
+            assert_eq!(framing.read_message(&mut input).unwrap(), None);
+        }
+
+        let mut buffer = Vec::new();
+        Framing::ContentLength
+            .write_message(&mut buffer, message)
+            .unwrap();
+        let expected = format!("Content-Length: {}\r\n\r\n{message}", message.len());
+        assert_eq!(String::from_utf8(buffer).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_serve_content_length() {
+        let transport = StdioTransport::new(initialized(Server::new("test-server", "1.0.0")))
+            .with_framing(Framing::ContentLength);
+        let ping = r#"{"jsonrpc":"2.0","id":7,"method":"ping"}"#;
+        let input = format!(
+            "Content-Type: application/json\r\ncontent-length: {}\r\n\r\n{ping}",
+            ping.len()
+        );
+
+        let mut output = Vec::new();
+        transport.serve(input.as_bytes(), &mut output).unwrap();
+        let response = Framing::ContentLength
+            .read_message(&mut output.as_slice())
+            .unwrap()
+            .unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["id"], 7);
+
+        let truncated = "Content-Length: 99\r\n\r\n{}";
+        assert!(transport.serve(truncated.as_bytes(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_oversized_messages_are_rejected() {
+        let huge = format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX);
+        let err = Framing::ContentLength
+            .read_message(&mut huge.as_bytes())
+            .unwrap_err();
+        assert!(err.to_string().contains("size limit"));
+
+        let transport = StdioTransport::new(initialized(Server::new("test-server", "1.0.0")))
+            .with_max_message_size(8);
+        let long_line = format!("{}\n", "x".repeat(20));
+        assert!(transport.serve(long_line.as_bytes(), Vec::new()).is_err());
+
+        let mut fits = "Content-Length: 2\r\n\r\n{}".as_bytes();
+        assert_eq!(
+            Framing::ContentLength
+                .read_message_with_limit(&mut fits, 2)
+                .unwrap()
+                .as_deref(),
+            Some("{}")
+        );
+    }
+}
@@ -0,0 +1,340 @@
+#[cfg(feature = "async")]
+use crate::{stdio::framing_error, DEFAULT_MAX_MESSAGE_SIZE};
+use crate::{unhandled_message, McpError, Session};
+#[cfg(feature = "async")]
+use std::future::Future;
+
+/// A channel carrying whole JSON-RPC messages to and from the client, which
+/// `Session::serve_transport` drives. Wrappers such as `LoggingTransport`
+/// decorate one without the session knowing.
+pub trait Transport {
+    /// The next message, or `None` once the client has gone away.
+    fn receive(&mut self) -> Result<Option<String>, McpError>;
+
+    fn send(&mut self, message: &str) -> Result<(), McpError>;
+
+    /// Pushes out whatever `send` buffered; called once per handled message.
+    fn flush(&mut self) -> Result<(), McpError> {
+        Ok(())
+    }
+}
+
+/// The async counterpart of `Transport`, driven by
+/// `Session::serve_transport_async`. `receive` must be cancel safe: the serve
+/// loop drops it unfinished whenever something else is ready first.
+#[cfg(feature = "async")]
+pub trait AsyncTransport {
+    fn receive(&mut self) -> impl Future<Output = Result<Option<String>, McpError>>;
+
+    fn send(&mut self, message: &str) -> impl Future<Output = Result<(), McpError>>;
+
+    fn flush(&mut self) -> impl Future<Output = Result<(), McpError>> {
+        async { Ok(()) }
+    }
+}
+
+/// Newline-delimited messages over an async reader and writer, as
+/// `Session::serve_async` uses. Like `FramedTransport`, it rejects messages
+/// over `DEFAULT_MAX_MESSAGE_SIZE` bytes unless given another limit.
+#[cfg(feature = "async")]
+pub struct LinesTransport<R, W> {
+    input: R,
+    output: W,
+    // The part of the current line read so far, kept across cancelled receives
+    line: Vec<u8>,
+    max_message_size: usize,
+}
+
+#[cfg(feature = "async")]
+impl<R, W> LinesTransport<R, W>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    pub fn new(input: R, output: W) -> Self {
+        LinesTransport {
+            input,
+            output,
+            line: Vec::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Caps the size of an incoming message; a larger one fails `receive`.
+    pub fn with_max_message_size(mut self, max_size: usize) -> Self {
+        self.max_message_size = max_size;
+        self
+    }
+
+    // The message in a complete line, or `None` for a blank one
+    fn take_line(&mut self) -> Result<Option<String>, McpError> {
+        let line = String::from_utf8(std::mem::take(&mut self.line))
+            .map_err(|_| framing_error("message is not valid UTF-8"))?;
+        let message = line.trim_end_matches(['\r', '\n']);
+        if message.len() > self.max_message_size {
+            return Err(framing_error("message exceeds the size limit"));
+        }
+        Ok((!message.trim().is_empty()).then(|| message.to_string()))
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R, W> AsyncTransport for LinesTransport<R, W>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    async fn receive(&mut self) -> Result<Option<String>, McpError> {
+        use tokio::io::AsyncBufReadExt;
+
+        // Only `fill_buf` is awaited, and it is cancel safe; what it returned
+        // is moved into `self.line` before the next await
+        loop {
+            let available = self.input.fill_buf().await?;
+            if available.is_empty() {
+                // A last line without a newline still counts
+                if self.line.is_empty() {
+                    return Ok(None);
+                }
+                return self.take_line();
+            }
+            let (used, complete) = match available.iter().position(|&byte| byte == b'\n') {
+                Some(end) => (end + 1, true),
+                None => (available.len(), false),
+            };
+            self.line.extend_from_slice(&available[..used]);
+            self.input.consume(used);
+
+            // Room for the line ending, as in `Framing::read_message_with_limit`
+            if self.line.len() > self.max_message_size + 2 {
+                self.line.clear();
+                return Err(framing_error("message exceeds the size limit"));
+            }
+            if complete {
+                if let Some(message) = self.take_line()? {
+                    return Ok(Some(message));
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, message: &str) -> Result<(), McpError> {
+        use tokio::io::AsyncWriteExt;
+
+        self.output.write_all(message.as_bytes()).await?;
+        self.output.write_all(b"\n").await?;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), McpError> {
+        use tokio::io::AsyncWriteExt;
+
+        Ok(self.output.flush().await?)
+    }
+}
+
+/// Which way a message handed to a logging sink was travelling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+pub(crate) type MessageSink = Box<dyn Fn(Direction, &str) + Send + Sync>;
+
+/// Hands every message received or sent through `inner` to `sink`, for
+/// debugging, while delegating to `inner`. Wraps any `Transport`, and any
+/// `AsyncTransport` with the `async` feature.
+pub struct LoggingTransport<T> {
+    inner: T,
+    sink: MessageSink,
+}
+
+impl<T> LoggingTransport<T> {
+    pub fn new<F>(inner: T, sink: F) -> Self
+    where
+        F: Fn(Direction, &str) + Send + Sync + 'static,
+    {
+        LoggingTransport {
+            inner,
+            sink: Box::new(sink),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Transport> Transport for LoggingTransport<T> {
+    fn receive(&mut self) -> Result<Option<String>, McpError> {
+        let message = self.inner.receive()?;
+        if let Some(message) = &message {
+            (self.sink)(Direction::Inbound, message);
+        }
+        Ok(message)
+    }
+
+    fn send(&mut self, message: &str) -> Result<(), McpError> {
+        (self.sink)(Direction::Outbound, message);
+        self.inner.send(message)
+    }
+
+    fn flush(&mut self) -> Result<(), McpError> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncTransport> AsyncTransport for LoggingTransport<T> {
+    async fn receive(&mut self) -> Result<Option<String>, McpError> {
+        // Logged only once received, so a dropped receive logs nothing
+        let message = self.inner.receive().await?;
+        if let Some(message) = &message {
+            (self.sink)(Direction::Inbound, message);
+        }
+        Ok(message)
+    }
+
+    async fn send(&mut self, message: &str) -> Result<(), McpError> {
+        (self.sink)(Direction::Outbound, message);
+        self.inner.send(message).await
+    }
+
+    async fn flush(&mut self) -> Result<(), McpError> {
+        self.inner.flush().await
+    }
+}
+
+impl Session {
+    /// Serves messages from `transport`, sending each response followed by
+    /// any queued requests and notifications. Returns when the transport runs
+    /// out or after the client sends `notifications/exit`. Only I/O errors end
+    /// the loop early.
+    pub fn serve_transport<T: Transport>(&self, transport: &mut T) -> Result<(), McpError> {
+        while let Some(message) = transport.receive()? {
+            match self.handle_message(&message) {
+                Ok(Some(response)) => transport.send(&response)?,
+                Ok(None) => {}
+                Err(McpError::Io(err)) => return Err(McpError::Io(err)),
+                Err(_) => transport.send(&self.to_json(&unhandled_message(&message))?)?,
+            }
+            for request in self.drain_outgoing_requests() {
+                transport.send(&self.to_json(&request)?)?;
+            }
+            for notification in self.drain_notifications() {
+                transport.send(&self.to_json(&notification)?)?;
+            }
+            transport.flush()?;
+
+            if self.should_exit() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::initialized;
+    use crate::Server;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    // Replays `inbound` and keeps what is sent
+    #[derive(Default)]
+    struct MockTransport {
+        inbound: VecDeque<String>,
+        sent: Vec<String>,
+    }
+
+    impl Transport for MockTransport {
+        fn receive(&mut self) -> Result<Option<String>, McpError> {
+            Ok(self.inbound.pop_front())
+        }
+
+        fn send(&mut self, message: &str) -> Result<(), McpError> {
+            self.sent.push(message.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_logging_transport() {
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&logged);
+        let ping = r#"{"jsonrpc":"2.0","id":7,"method":"ping"}"#;
+        let mock = MockTransport {
+            inbound: VecDeque::from([ping.to_string()]),
+            ..MockTransport::default()
+        };
+        let mut transport = LoggingTransport::new(mock, move |direction, message: &str| {
+            sink.lock().unwrap().push((direction, message.to_string()));
+        });
+
+        let server = initialized(Server::new("test-server", "1.0.0"));
+        server.serve_transport(&mut transport).unwrap();
+
+        let mock = transport.into_inner();
+        assert_eq!(mock.sent.len(), 1);
+        let logged = logged.lock().unwrap();
+        assert_eq!(logged.len(), 2);
+        assert_eq!(logged[0], (Direction::Inbound, ping.to_string()));
+        assert_eq!(logged[1], (Direction::Outbound, mock.sent[0].clone()));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_logging_async_transport() {
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&logged);
+        let ping = r#"{"jsonrpc":"2.0","id":7,"method":"ping"}"#;
+        let input = format!("{ping}\n");
+        let mut output = Vec::new();
+        let transport = LoggingTransport::new(
+            LinesTransport::new(input.as_bytes(), &mut output),
+            move |direction, message: &str| {
+                sink.lock().unwrap().push((direction, message.to_string()));
+            },
+        );
+
+        let server = Server::new("test-server", "1.0.0");
+        server.serve_transport_async(transport).await.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let logged = logged.lock().unwrap();
+        assert_eq!(logged.len(), 2);
+        assert_eq!(logged[0], (Direction::Inbound, ping.to_string()));
+        assert_eq!(logged[1].0, Direction::Outbound);
+        assert_eq!(format!("{}\n", logged[1].1), output);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_lines_transport_rejects_oversized_messages() {
+        let input = format!("\n{{}}\r\n{}\n", "x".repeat(20));
+        let mut transport =
+            LinesTransport::new(input.as_bytes(), Vec::new()).with_max_message_size(8);
+        assert_eq!(transport.receive().await.unwrap().as_deref(), Some("{}"));
+        let err = transport.receive().await.unwrap_err();
+        assert!(err.to_string().contains("size limit"));
+
+        // A line split across reads is put together, and a last one needs no newline
+        let input = tokio::io::BufReader::with_capacity(4, "{\"a\":1}\n[]".as_bytes());
+        let mut transport = LinesTransport::new(input, Vec::new());
+        assert_eq!(
+            transport.receive().await.unwrap().as_deref(),
+            Some("{\"a\":1}")
+        );
+        assert_eq!(transport.receive().await.unwrap().as_deref(), Some("[]"));
+        assert_eq!(transport.receive().await.unwrap(), None);
+
+        let server = Server::new("test-server", "1.0.0");
+        let oversized = format!("{}\n", " ".repeat(crate::DEFAULT_MAX_MESSAGE_SIZE + 3));
+        assert!(server
+            .serve_async(oversized.as_bytes(), Vec::new())
+            .await
+            .is_err());
+    }
+}
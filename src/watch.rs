@@ -0,0 +1,53 @@
+use crate::ServerState;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::error::Error;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub(crate) const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(100);
+
+// Keeps a filesystem watch alive for a single resource. Dropping it stops the
+// watch and lets the debounce thread exit.
+pub(crate) struct ResourceWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl ResourceWatcher {
+    pub(crate) fn new(
+        uri: String,
+        path: &Path,
+        debounce: Duration,
+        state: Arc<Mutex<ServerState>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if let Ok(event) = event {
+                    if event.kind.is_modify() || event.kind.is_create() {
+                        let _ = tx.send(());
+                    }
+                }
+            })?;
+        watcher.watch(path, RecursiveMode::NonRecursive)?;
+
+        thread::spawn(move || {
+            // Block until the first change, then wait for a quiet period before
+            // emitting a single notification for the whole burst.
+            while rx.recv().is_ok() {
+                loop {
+                    match rx.recv_timeout(debounce) {
+                        Ok(()) => continue,
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                state.lock().unwrap().resource_updated(&uri);
+            }
+        });
+
+        Ok(ResourceWatcher { _watcher: watcher })
+    }
+}